@@ -0,0 +1,157 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    manifest::BookManifest,
+    page_header::{page_checksum, PageHeader, PAGE_FORMAT_VERSION, PAGE_MAGIC},
+};
+
+/// `path` with `suffix` appended to its file name, for work that is renamed into place.
+pub fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+
+    path.with_file_name(name)
+}
+
+/// Copy the directory tree at `src` to `dest`, syncing every file. Temporary
+/// files left behind by an interrupted write are skipped.
+pub fn copy_dir(src: &Path, dest: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(dest)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to ensure {:?}", dest)))?;
+
+    for entry in fs::read_dir(src)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to read {:?}", src)))?
+    {
+        let entry = entry.map_err(|e| anyhow::anyhow!(e).context("failed to read entry"))?;
+        let from = entry.path();
+        let to = dest.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_dir(&from, &to)?;
+        } else if from.extension().is_none_or(|ext| ext != "tmp") {
+            fs::copy(&from, &to).map_err(|e| {
+                anyhow::anyhow!(e).context(format!("failed to copy {:?} to {:?}", from, to))
+            })?;
+            File::open(&to)?.sync_all()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy `src` to `dest`, which must not exist, through a staging directory so
+/// that `dest` only ever appears complete.
+pub fn copy_dir_atomic(src: &Path, dest: &Path) -> anyhow::Result<()> {
+    if dest.exists() {
+        anyhow::bail!("{:?} already exists", dest);
+    }
+
+    let staging = sibling_path(dest, "partial");
+
+    if staging.exists() {
+        fs::remove_dir_all(&staging)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to remove {:?}", staging)))?;
+    }
+
+    if let Err(e) = copy_dir(src, &staging) {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    publish_dir(&staging, dest)
+}
+
+/// Rename a fully written directory into place and make the rename durable.
+pub fn publish_dir(staging: &Path, dest: &Path) -> anyhow::Result<()> {
+    fs::rename(staging, dest)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to publish {:?}", dest)))?;
+
+    if let Some(parent) = dest.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Check that the book copied to `book_dir` has its manifest and that every
+/// page is intact and holds the entries `fingerprint` identifies, without
+/// knowing their types.
+pub fn validate_book(book_dir: &Path, fingerprint: u64) -> anyhow::Result<()> {
+    let manifest = BookManifest::load(book_dir)?
+        .ok_or_else(|| anyhow::anyhow!("book {:?} has no manifest", book_dir))?;
+
+    let pages_dir = book_dir.join("pages");
+
+    for entry in fs::read_dir(&pages_dir)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to read {:?}", pages_dir)))?
+    {
+        let path = entry
+            .map_err(|e| anyhow::anyhow!(e).context("failed to read entry"))?
+            .path();
+
+        if path
+            .file_name()
+            .and_then(|name| name.to_str()?.parse::<u32>().ok())
+            .is_none()
+        {
+            anyhow::bail!("{:?} is not a page file", path);
+        }
+
+        let content = fs::read(&path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to read {:?}", path)))?;
+
+        validate_page(&content, manifest.page_size, fingerprint)
+            .map_err(|e| e.context(format!("page {:?} is damaged", path)))?;
+    }
+
+    Ok(())
+}
+
+fn validate_page(content: &[u8], page_size: usize, fingerprint: u64) -> anyhow::Result<()> {
+    let header = PageHeader::read(content)?;
+
+    if header.magic != PAGE_MAGIC {
+        anyhow::bail!("page has bad magic {:02x?}, not a page file", header.magic);
+    }
+
+    if header.version != PAGE_FORMAT_VERSION {
+        anyhow::bail!(
+            "page format version {} is not supported, expected {}",
+            header.version,
+            PAGE_FORMAT_VERSION
+        );
+    }
+
+    if header.page_size as usize != page_size || content.len() != page_size {
+        anyhow::bail!(
+            "page was written with a page size of {} ({} bytes on disk), expected {}",
+            header.page_size,
+            content.len(),
+            page_size
+        );
+    }
+
+    if header.fingerprint != fingerprint {
+        anyhow::bail!(
+            "page type fingerprint {:#018x} does not match the catalog ({:#018x})",
+            header.fingerprint,
+            fingerprint
+        );
+    }
+
+    let computed = page_checksum(content);
+
+    if header.checksum != computed {
+        anyhow::bail!(
+            "page checksum is {:#018x}, expected {:#018x}",
+            computed,
+            header.checksum
+        );
+    }
+
+    Ok(())
+}
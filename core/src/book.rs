@@ -1,28 +1,292 @@
-use std::sync::Arc;
+use std::{
+    io::{Read, Write},
+    ops::RangeBounds,
+    path::Path,
+    sync::{Arc, Weak},
+};
 
-use parking_lot::{ArcRwLockUpgradableReadGuard, ArcRwLockWriteGuard, RawRwLock, RwLock};
+use parking_lot::{
+    ArcRwLockUpgradableReadGuard, ArcRwLockWriteGuard, RawRwLock, RwLock, RwLockReadGuard,
+};
 
-use crate::{book_inner::BookInner, BookId};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    book_inner::{BatchOutcome, BookInner},
+    book_key::BookKey,
+    changes::Subscription,
+    cursor::{Cursor, CursorPos, RangeCursor},
+    heap::{HeapRef, HeapStr, HeapVec},
+    ndjson::{self, ImportReport, OnConflict},
+    options::BookOptions,
+    persistable::Persistable,
+    secondary_index::IndexKey,
+    transaction::Transaction,
+    verify::VerifyReport,
+    BookId, Key,
+};
 
 #[derive(Debug)]
-pub struct Book<T>(Arc<RwLock<BookInner<T>>>);
+pub struct Book<T: Persistable, K: BookKey = Key>(Arc<RwLock<BookInner<T, K>>>);
 
-impl<T> Clone for Book<T> {
+impl<T: Persistable, K: BookKey> Clone for Book<T, K> {
     fn clone(&self) -> Self {
         Self(Arc::clone(&self.0))
     }
 }
 
-impl<T> Book<T> {
+/// A handle that does not keep its book open.
+#[derive(Debug)]
+pub struct WeakBook<T: Persistable, K: BookKey = Key>(Weak<RwLock<BookInner<T, K>>>);
+
+impl<T: Persistable, K: BookKey> Clone for WeakBook<T, K> {
+    fn clone(&self) -> Self {
+        Self(Weak::clone(&self.0))
+    }
+}
+
+impl<T: Persistable, K: BookKey> WeakBook<T, K> {
+    pub fn upgrade(&self) -> Option<Book<T, K>> {
+        self.0.upgrade().map(Book)
+    }
+}
+
+impl<T: Persistable, K: BookKey> Book<T, K> {
     pub fn new(id: BookId) -> anyhow::Result<Self> {
         Ok(Book(Arc::new(RwLock::new(BookInner::new(id)?))))
     }
 
-    pub fn read(&self) -> ArcRwLockUpgradableReadGuard<RawRwLock, BookInner<T>> {
+    pub fn with_options(id: BookId, options: BookOptions) -> anyhow::Result<Self> {
+        Ok(Book(Arc::new(RwLock::new(BookInner::with_options(
+            id, options,
+        )?))))
+    }
+
+    pub fn new_in(root: &Path, id: BookId) -> anyhow::Result<Self> {
+        Ok(Book(Arc::new(RwLock::new(BookInner::new_in(root, id)?))))
+    }
+
+    pub fn with_options_in(root: &Path, id: BookId, options: BookOptions) -> anyhow::Result<Self> {
+        Ok(Book(Arc::new(RwLock::new(BookInner::with_options_in(
+            root, id, options,
+        )?))))
+    }
+
+    pub fn downgrade(&self) -> WeakBook<T, K> {
+        WeakBook(Arc::downgrade(&self.0))
+    }
+
+    pub fn read(&self) -> ArcRwLockUpgradableReadGuard<RawRwLock, BookInner<T, K>> {
         self.0.upgradable_read_arc()
     }
 
-    pub fn write(&self) -> ArcRwLockWriteGuard<RawRwLock, BookInner<T>> {
+    pub fn write(&self) -> ArcRwLockWriteGuard<RawRwLock, BookInner<T, K>> {
         self.0.write_arc()
     }
+
+    /// Durably flush every dirty page and truncate the write-ahead log.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        self.write().checkpoint()
+    }
+
+    /// Schedule dirty pages for writeback without waiting for it to complete.
+    pub fn flush_async(&self) -> anyhow::Result<()> {
+        self.shared().flush_async()
+    }
+
+    /// Copy the book to `dest` as it stands now; the copy opens like any other
+    /// book. Writers wait while the files are copied, readers carry on.
+    pub fn snapshot(&self, dest: impl AsRef<Path>) -> anyhow::Result<()> {
+        let mut book_guard = self.write();
+        book_guard.checkpoint()?;
+
+        let book_guard = ArcRwLockWriteGuard::downgrade(book_guard);
+        book_guard.copy_to(dest.as_ref())
+    }
+
+    pub fn put_str(&self, val: &str) -> anyhow::Result<HeapStr> {
+        self.write().put_str(val)
+    }
+
+    pub fn get_str(&self, handle: HeapStr) -> anyhow::Result<String> {
+        self.read().get_str(handle)
+    }
+
+    pub fn put_vec<V: Persistable>(&self, vals: &[V]) -> anyhow::Result<HeapVec<V>> {
+        self.write().put_vec(vals)
+    }
+
+    pub fn get_vec<V: Persistable>(&self, handle: HeapVec<V>) -> anyhow::Result<Vec<V>> {
+        self.read().get_vec(handle)
+    }
+
+    pub fn free(&self, handle: impl Into<HeapRef>) -> anyhow::Result<()> {
+        self.write().free(handle)
+    }
+
+    /// Shared access for internal readers that never need to upgrade.
+    pub(crate) fn shared(&self) -> RwLockReadGuard<'_, BookInner<T, K>> {
+        self.0.read()
+    }
+
+    /// Iterate over every live entry in the book, in page order.
+    pub fn iter(&self) -> Cursor<T, K> {
+        Cursor::new(self.clone(), CursorPos::default())
+    }
+
+    /// Resume iteration from a position previously returned by `Cursor::pos`.
+    pub fn cursor_at(&self, pos: CursorPos) -> Cursor<T, K> {
+        Cursor::new(self.clone(), pos)
+    }
+
+    /// Iterate over the entries whose keys fall within `range`, in `K` order.
+    pub fn range(&self, range: impl RangeBounds<K>) -> RangeCursor<T, K> {
+        RangeCursor::new(
+            self.clone(),
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            false,
+        )
+    }
+
+    /// Like `range`, but from the highest key down.
+    pub fn range_rev(&self, range: impl RangeBounds<K>) -> RangeCursor<T, K> {
+        RangeCursor::new(
+            self.clone(),
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            true,
+        )
+    }
+
+    pub fn get(&self, key: K) -> anyhow::Result<Option<T>> {
+        self.read().get(key)
+    }
+
+    pub fn update<R>(&self, key: K, f: impl FnOnce(&mut T) -> R) -> anyhow::Result<R> {
+        self.write().update(key, f)
+    }
+
+    pub fn upsert(&self, key: K, val: T) -> anyhow::Result<Option<T>> {
+        self.write().upsert(key, val)
+    }
+
+    pub fn insert_many(
+        &self,
+        items: impl IntoIterator<Item = (K, T)>,
+    ) -> anyhow::Result<Vec<BatchOutcome<K>>> {
+        self.write().insert_many(items)
+    }
+
+    pub fn delete_many(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> anyhow::Result<Vec<BatchOutcome<K>>> {
+        self.write().delete_many(keys)
+    }
+
+    /// Stream every entry to `writer` as NDJSON in key order, holding the lock
+    /// only for one small batch at a time. Returns the number of entries written.
+    pub fn export_ndjson(&self, writer: impl Write) -> anyhow::Result<usize>
+    where
+        T: Serialize,
+        K: Serialize,
+    {
+        ndjson::export(self, writer)
+    }
+
+    /// Stream entries written by `export_ndjson` into the book, committing them
+    /// in batches. Entries on the lines before an error stay in the book.
+    pub fn import_ndjson(
+        &self,
+        reader: impl Read,
+        on_conflict: OnConflict,
+    ) -> anyhow::Result<ImportReport>
+    where
+        T: DeserializeOwned,
+        K: DeserializeOwned,
+    {
+        ndjson::import(self, reader, on_conflict)
+    }
+
+    /// Register a secondary index; see `BookInner::create_index`.
+    pub fn create_index(
+        &self,
+        name: &str,
+        extractor: impl Fn(&T) -> IndexKey + Send + Sync + 'static,
+        unique: bool,
+    ) -> anyhow::Result<()> {
+        self.write().create_index(name, extractor, unique)
+    }
+
+    /// Declare a unique constraint; see `BookInner::add_unique_constraint`.
+    pub fn add_unique_constraint(
+        &self,
+        name: &str,
+        extractor: impl Fn(&T) -> IndexKey + Send + Sync + 'static,
+    ) -> anyhow::Result<()> {
+        self.write().add_unique_constraint(name, extractor)
+    }
+
+    pub fn rebuild_index(&self, name: &str) -> anyhow::Result<()> {
+        self.write().rebuild_index(name)
+    }
+
+    pub fn drop_index(&self, name: &str) -> anyhow::Result<()> {
+        self.write().drop_index(name)
+    }
+
+    pub fn lookup_by_index(
+        &self,
+        name: &str,
+        value: impl Into<IndexKey>,
+    ) -> anyhow::Result<Vec<(K, T)>> {
+        self.read().lookup_by_index(name, value)
+    }
+
+    pub fn index_range(
+        &self,
+        name: &str,
+        range: impl RangeBounds<IndexKey>,
+    ) -> anyhow::Result<Vec<(K, T)>> {
+        self.read().index_range(name, range)
+    }
+
+    /// Stream the changes committed to the book from now on; see `BookInner::subscribe`.
+    pub fn subscribe(&self) -> Subscription<T, K> {
+        self.write().subscribe()
+    }
+
+    pub fn subscribe_from(&self, seq: u64) -> anyhow::Result<Subscription<T, K>> {
+        self.write().subscribe_from(seq)
+    }
+
+    /// Run `f` as a single all-or-nothing transaction. The book stays write
+    /// locked for the duration, so readers never observe a partial commit.
+    pub fn transaction<R>(
+        &self,
+        f: impl FnOnce(&mut Transaction<'_, T, K>) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        self.write().transaction(f)
+    }
+
+    /// Checkpoint, then check every page for corruption and inconsistencies.
+    pub fn verify(&self) -> anyhow::Result<VerifyReport<K>> {
+        let mut book_guard = self.write();
+        book_guard.checkpoint()?;
+
+        Ok(book_guard.verify())
+    }
+
+    /// Compact the book one page at a time, releasing the lock between steps so
+    /// readers and writers can interleave. Returns the number of pages reclaimed.
+    pub fn compact(&self) -> anyhow::Result<usize> {
+        let mut reclaimed = 0;
+
+        while self.write().compact_step()?.is_some() {
+            reclaimed += 1;
+        }
+
+        Ok(reclaimed)
+    }
 }
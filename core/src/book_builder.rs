@@ -0,0 +1,198 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backup::publish_dir,
+    book_key::BookKey,
+    free_space::FreeSpaceMap,
+    key_index::KeyIndex,
+    manifest::BookManifest,
+    options::BookOptions,
+    page_header::{page_checksum, PageHeader},
+    page_layout::PageLayout,
+    persistable::Persistable,
+    BookId, Idx, Key, DATA_DIR,
+};
+
+/// Writes a new book straight from a stream of entries sorted by key, without
+/// going through the write-ahead log or the buffer pool.
+///
+/// Pages are packed full and written out one at a time as they fill up, and
+/// the key index is written in a single pass at the end. Everything is staged
+/// in a directory next to the book's and only renamed into place by `finish`,
+/// so the book either appears complete or not at all. Once finished it opens
+/// with `Book::new_in` like any other book.
+#[derive(Debug)]
+pub struct BookBuilder<T: Persistable, K: BookKey = Key> {
+    dest: PathBuf,
+    staging: PathBuf,
+    options: BookOptions,
+    layout: PageLayout<T, K>,
+    buf: Vec<u8>,
+    len: usize,
+    keys: Vec<(K, Idx)>,
+    fill: FreeSpaceMap,
+    finished: bool,
+}
+
+impl<T: Persistable, K: BookKey> BookBuilder<T, K> {
+    pub fn new(id: BookId, options: BookOptions) -> anyhow::Result<Self> {
+        Self::new_in(&DATA_DIR, id, options)
+    }
+
+    /// Start building the book that will live under `root/books/<id>`, which
+    /// must not exist yet.
+    pub fn new_in(root: &Path, id: BookId, options: BookOptions) -> anyhow::Result<Self> {
+        let books_dir = root.join("books");
+        let dest = books_dir.join(id.val.to_string());
+        let staging = books_dir.join(format!("{}.building", id.val));
+
+        if dest.exists() {
+            anyhow::bail!("book {:?} already exists", dest);
+        }
+
+        if u32::try_from(options.page_size).is_err() {
+            anyhow::bail!("page size of {} bytes is too large", options.page_size);
+        }
+
+        let layout = PageLayout::<T, K>::new(options.page_size);
+
+        if layout.cap == 0 {
+            anyhow::bail!(
+                "page size of {} bytes cannot hold a single `{}` entry",
+                options.page_size,
+                std::any::type_name::<T>()
+            );
+        }
+
+        // note: whatever a previous, abandoned build left behind is discarded
+        if staging.exists() {
+            fs::remove_dir_all(&staging).map_err(|e| {
+                anyhow::anyhow!(e).context(format!("failed to remove {:?}", staging))
+            })?;
+        }
+
+        fs::create_dir_all(staging.join("pages"))
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to ensure {:?}", staging)))?;
+
+        Ok(BookBuilder {
+            dest,
+            staging,
+            buf: vec![0; options.page_size],
+            options,
+            layout,
+            len: 0,
+            keys: vec![],
+            fill: FreeSpaceMap::new(layout.cap),
+            finished: false,
+        })
+    }
+
+    /// The number of entries pushed so far.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Append an entry. Keys must arrive in strictly ascending order.
+    pub fn push(&mut self, key: K, val: T) -> anyhow::Result<()> {
+        if let Some((last, _)) = self.keys.last() {
+            if key <= *last {
+                anyhow::bail!(
+                    "keys must be strictly ascending, but {:?} follows {:?}",
+                    key,
+                    last
+                );
+            }
+        }
+
+        let page_idx = Idx::new(self.fill.page_count() as u32);
+        let data_ptr = self.buf.as_mut_ptr();
+
+        unsafe {
+            let mut entry = self.layout.nth_ptr_mut(data_ptr, self.len);
+            entry.replace_key(key);
+            entry.replace_val(val);
+
+            self.layout.set_nth_vacant(data_ptr, self.len, false);
+        }
+
+        self.keys.push((key, page_idx));
+        self.len += 1;
+
+        if self.len == self.layout.cap {
+            self.write_page()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn extend(&mut self, entries: impl IntoIterator<Item = (K, T)>) -> anyhow::Result<()> {
+        for (key, val) in entries {
+            self.push(key, val)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stamp the page being filled and write it to its file in one go.
+    fn write_page(&mut self) -> anyhow::Result<()> {
+        let page_idx = Idx::new(self.fill.page_count() as u32);
+        let path = self.staging.join("pages").join(page_idx.val.to_string());
+
+        let mut header = PageHeader::expected::<T, K>(self.options.page_size);
+
+        unsafe { header.write(self.buf.as_mut_ptr()) };
+        header.checksum = page_checksum(&self.buf);
+        unsafe { header.write(self.buf.as_mut_ptr()) };
+
+        let mut file = File::create(&path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to create {:?}", path)))?;
+        file.write_all(&self.buf)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", path)))?;
+        file.sync_all()?;
+
+        self.fill.set(page_idx, self.len);
+        self.buf.fill(0);
+        self.len = 0;
+
+        Ok(())
+    }
+
+    /// Write the last page, the key index, free space map and manifest, then
+    /// publish the book under its final directory.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        if self.len > 0 {
+            self.write_page()?;
+        }
+
+        BookManifest::new(&self.options).store(&self.staging)?;
+        self.fill.store(&self.staging)?;
+        KeyIndex::build(&self.staging, std::mem::take(&mut self.keys))?;
+
+        if self.dest.exists() {
+            anyhow::bail!("book {:?} already exists", self.dest);
+        }
+
+        publish_dir(&self.staging, &self.dest)?;
+        self.finished = true;
+
+        Ok(())
+    }
+}
+
+impl<T: Persistable, K: BookKey> Drop for BookBuilder<T, K> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = fs::remove_dir_all(&self.staging);
+        }
+    }
+}
@@ -1,26 +1,147 @@
 use std::{
-    collections::{BTreeSet, HashMap},
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
 };
 
-use crate::{page::Page, page_layout::PAGE_SIZE, BookId, Idx, Key, DATA_DIR};
+use crate::{
+    backup::copy_dir_atomic,
+    book_key::BookKey,
+    buffer_pool::{BufferPool, PoolStats},
+    changes::{ChangeFeed, Subscription},
+    free_space::FreeSpaceMap,
+    heap::{bytes_to_vec, slice_bytes, Heap, HeapRef, HeapStr, HeapVec},
+    key_index::KeyIndex,
+    manifest::BookManifest,
+    options::{BookOptions, SyncPolicy},
+    page::Page,
+    page_layout::PageLayout,
+    persistable::Persistable,
+    secondary_index::{ConstraintViolation, IndexExtractor, IndexKey, SecondaryIndex},
+    transaction::Transaction,
+    verify::{Problem, VerifyReport},
+    wal::{Wal, WalRecord, WAL_CHECKPOINT_BYTES},
+    BookId, Idx, Key, DATA_DIR,
+};
+
+/// What a single `BookInner::compact_step` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionStep {
+    /// The page that was emptied and whose file was deleted.
+    pub reclaimed: Idx,
+    /// How many entries were moved out of it.
+    pub relocated: usize,
+}
+
+/// What a batch write did with one of its items.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOutcome<K: BookKey = Key> {
+    /// The item was written.
+    Applied,
+    /// An insert whose key is already in the book or earlier in the batch.
+    KeyExists,
+    /// A delete whose key is not in the book or was deleted earlier in the batch.
+    KeyNotFound,
+    /// An insert rejected by a unique index.
+    Violation(ConstraintViolation<K>),
+}
+
+impl<K: BookKey> BatchOutcome<K> {
+    #[inline]
+    pub fn is_applied(&self) -> bool {
+        matches!(self, BatchOutcome::Applied)
+    }
+}
 
 #[derive(Debug)]
-pub struct BookInner<T> {
+pub struct BookInner<T: Persistable, K: BookKey = Key> {
     id: BookId,
-    pages: Vec<Page<T>>,
-    key_lookup: HashMap<Key, Idx>,
-    partial: BTreeSet<Idx>,
+    dir: PathBuf,
+    options: BookOptions,
+    pool: BufferPool<T, K>,
+    keys: KeyIndex<K>,
+    fill: FreeSpaceMap,
+    indexes: BTreeMap<String, SecondaryIndex<T, K>>,
+    dormant: BTreeSet<String>,
+    constraints: BTreeSet<String>,
+    wal: Wal,
+    heap: Heap,
+    pending_free: Vec<HeapRef>,
+    last_checkpoint: Instant,
+    changes: ChangeFeed<T, K>,
 }
 
-impl<T> BookInner<T> {
+impl<T: Persistable, K: BookKey> BookInner<T, K> {
     pub fn new(id: BookId) -> anyhow::Result<Self> {
-        let pages_dir = DATA_DIR.join(format!("books/{}/pages", id.val));
+        Self::new_in(&DATA_DIR, id)
+    }
+
+    pub fn with_options(id: BookId, options: BookOptions) -> anyhow::Result<Self> {
+        Self::with_options_in(&DATA_DIR, id, options)
+    }
+
+    /// Open the book stored under `root/books/<id>`.
+    pub fn new_in(root: &Path, id: BookId) -> anyhow::Result<Self> {
+        Self::with_options_in(root, id, BookOptions::default())
+    }
+
+    pub fn with_options_in(
+        root: &Path,
+        id: BookId,
+        mut options: BookOptions,
+    ) -> anyhow::Result<Self> {
+        let dir = root.join(format!("books/{}", id.val));
+        let pages_dir = dir.join("pages");
+
+        let constraints = if let Some(manifest) = BookManifest::load(&dir)? {
+            options.page_size = manifest.page_size;
+            manifest.unique_constraints
+        } else {
+            // note: without its manifest a book's page size cannot be trusted
+            let has_pages = match fs::read_dir(&pages_dir) {
+                Ok(mut entries) => entries.next().is_some(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+                Err(e) => {
+                    return Err(
+                        anyhow::anyhow!(e).context(format!("failed to read {:?}", pages_dir))
+                    )
+                }
+            };
+
+            if has_pages {
+                anyhow::bail!("book {:?} has pages but no manifest", dir);
+            }
+
+            if u32::try_from(options.page_size).is_err() {
+                anyhow::bail!("page size of {} bytes is too large", options.page_size);
+            }
+
+            if PageLayout::<T, K>::new(options.page_size).cap == 0 {
+                anyhow::bail!(
+                    "page size of {} bytes cannot hold a single `{}` entry",
+                    options.page_size,
+                    std::any::type_name::<T>()
+                );
+            }
+
+            fs::create_dir_all(&dir)
+                .map_err(|e| anyhow::anyhow!(e).context(format!("failed to ensure {:?}", dir)))?;
+
+            BookManifest::new(&options).store(&dir)?;
+            BTreeSet::new()
+        };
 
         fs::create_dir_all(&pages_dir)
             .map_err(|e| anyhow::anyhow!(e).context(format!("failed to ensure {:?}", pages_dir)))?;
 
-        let mut page_files = fs::read_dir(&pages_dir)
+        let page_size = options.page_size;
+        let cap = PageLayout::<T, K>::new(page_size).cap;
+
+        let live = fs::read_dir(&pages_dir)
             .map_err(|e| anyhow::anyhow!(e).context(format!("failed to read {:?}", pages_dir)))?
             .map(|entry| entry.map_err(|e| anyhow::anyhow!(e).context("failed to read entry")))
             .collect::<anyhow::Result<Vec<_>, _>>()?
@@ -29,134 +150,1352 @@ impl<T> BookInner<T> {
             .map(|entry| {
                 let path = entry.path();
                 let file_name = path.file_name().unwrap().to_str().unwrap();
-                let idx = file_name.parse::<u32>().unwrap();
-                (idx, path)
+                Idx::new(file_name.parse::<u32>().unwrap())
             })
-            .collect::<Vec<_>>();
+            .collect::<BTreeSet<_>>();
 
-        if page_files.is_empty() {
-            page_files.push((0, pages_dir.join("0")));
-        }
+        let pool = BufferPool::new(&pages_dir, page_size, options.page_cache_pages);
 
-        page_files.sort_unstable_by_key(|(idx, _)| *idx);
+        // note: a cleanly synced index and free space map describe the pages exactly,
+        // so they only have to be mapped as they are used
+        let clean = match KeyIndex::open(&dir)? {
+            Some(keys) => FreeSpaceMap::load(&dir, cap, &live)?.map(|fill| (keys, fill)),
+            None => None,
+        };
 
-        let mut key_lookup = HashMap::with_capacity(PAGE_SIZE * page_files.len());
-        let mut partial = BTreeSet::new();
+        let fresh = clean.is_some();
 
-        let pages = page_files
-            .into_iter()
-            .map(|(i, path)| -> anyhow::Result<Option<Page<T>>> {
-                let file = fs::OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .create(true)
-                    .open(&path)
-                    .map_err(|e| {
-                        anyhow::anyhow!(e).context(format!("failed to open {:?}", path))
-                    })?;
+        let (keys, fill) = match clean {
+            Some(clean) => {
+                // note: mapping one page is enough to catch a book opened with the wrong type
+                if let Some(first) = live.first() {
+                    pool.get(*first)?;
+                }
 
-                let page = if file.metadata()?.len() == 0 {
-                    Page::new(&file)?
-                } else {
-                    Page::parse(&file)?
-                };
+                clean
+            }
+            None => Self::scan(&dir, &pool, cap, &live)?,
+        };
 
-                let page_idx = Idx::new(i);
-                let page_guard = page.read();
+        let wal = Wal::open(&dir.join("wal"))?;
+        let heap = Heap::open(&dir.join("heap"))?;
+
+        let dormant = Self::dormant_indexes(&dir, fresh && wal.is_empty())?;
+
+        let changes = ChangeFeed::new(options.change_buffer);
+
+        let mut book = BookInner {
+            id,
+            dir,
+            options,
+            pool,
+            keys,
+            fill,
+            indexes: BTreeMap::new(),
+            dormant,
+            constraints,
+            wal,
+            heap,
+            pending_free: vec![],
+            last_checkpoint: Instant::now(),
+            changes,
+        };
+
+        if book.fill.page_count() == 0 {
+            book.alloc_page()?;
+        }
 
-                if !page_guard.is_full() {
-                    partial.insert(page_idx);
+        book.recover()?;
+
+        Ok(book)
+    }
+
+    /// Rebuild the key index and free space map by reading every page, after a
+    /// crash or for a book that predates them.
+    fn scan(
+        dir: &Path,
+        pool: &BufferPool<T, K>,
+        cap: usize,
+        live: &BTreeSet<Idx>,
+    ) -> anyhow::Result<(KeyIndex<K>, FreeSpaceMap)> {
+        let mut key_lookup = HashMap::new();
+        let mut fill = FreeSpaceMap::new(cap);
+
+        for page_idx in live.iter().copied() {
+            let page = pool.get(page_idx)?;
+            let mut stale = vec![];
+
+            for key in page.read().keys() {
+                if let Some(first) = key_lookup.insert(*key, page_idx) {
+                    key_lookup.insert(*key, first);
+                    stale.push(*key);
                 }
+            }
 
-                key_lookup.extend(page_guard.keys().map(|key| (*key, page_idx)));
+            let mut page_guard = page.write();
 
-                Ok(Some(page))
-            })
-            .collect::<anyhow::Result<Vec<_>, _>>()?
-            .into_iter()
-            .flatten()
-            .collect();
+            // note: a relocation interrupted by a crash can leave a key in two pages;
+            // the copies are identical, so keep the first and drop the rest
+            for key in stale {
+                page_guard.delete(key)?;
+            }
 
-        Ok(BookInner {
-            id,
-            pages,
-            key_lookup,
-            partial,
-        })
+            // note: pages may have been written back without a fresh checksum
+            // before the crash, so restamp all of them
+            page_guard.flush()?;
+            fill.set(page_idx, page_guard.len());
+        }
+
+        fill.store(dir)?;
+
+        Ok((KeyIndex::build(dir, key_lookup)?, fill))
+    }
+
+    /// Names of the secondary indexes stored in `dir`. Unless the book was
+    /// closed cleanly they may be stale, so they are deleted instead.
+    fn dormant_indexes(dir: &Path, clean: bool) -> anyhow::Result<BTreeSet<String>> {
+        let indexes_dir = SecondaryIndex::<T>::dir(dir);
+
+        if !indexes_dir.exists() {
+            return Ok(BTreeSet::new());
+        }
+
+        if !clean {
+            fs::remove_dir_all(&indexes_dir).map_err(|e| {
+                anyhow::anyhow!(e).context(format!("failed to remove {:?}", indexes_dir))
+            })?;
+
+            return Ok(BTreeSet::new());
+        }
+
+        Ok(fs::read_dir(&indexes_dir)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to read {:?}", indexes_dir)))?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| SecondaryIndex::<T>::validate_name(name).is_ok())
+            .collect())
+    }
+
+    /// Re-apply any logged mutations that may not have reached the pages, then checkpoint.
+    fn recover(&mut self) -> anyhow::Result<()> {
+        if self.wal.is_empty() {
+            return Ok(());
+        }
+
+        for record in self.wal.replay::<T, K>()? {
+            self.apply(record)
+                .map_err(|e| e.context("failed to replay wal"))?;
+        }
+
+        self.checkpoint()
+    }
+
+    #[inline]
+    pub fn id(&self) -> BookId {
+        self.id
+    }
+
+    #[inline]
+    pub fn options(&self) -> &BookOptions {
+        &self.options
     }
 
     pub fn len(&self) -> usize {
-        self.key_lookup.len()
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn has_key(&self, key: K) -> bool {
+        self.keys.contains_key(key)
+    }
+
+    /// The number of live pages, not counting ones reclaimed by compaction.
+    pub fn page_count(&self) -> usize {
+        self.fill.page_count()
+    }
+
+    /// The page stored under `page_idx`, mapped through the page cache. The page
+    /// stays pinned in the cache for as long as the returned handle is held.
+    pub fn page(&self, page_idx: Idx) -> anyhow::Result<Option<Page<T, K>>> {
+        if !self.fill.contains(page_idx) {
+            return Ok(None);
+        }
+
+        Ok(Some(self.pool.get(page_idx)?))
+    }
+
+    /// The first live page at or after `from`.
+    pub fn next_page(&self, from: Idx) -> anyhow::Result<Option<(Idx, Page<T, K>)>> {
+        match self.fill.pages().find(|page_idx| *page_idx >= from) {
+            Some(page_idx) => Ok(Some((page_idx, self.pool.get(page_idx)?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn cache_stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    fn page_of(&self, key: K) -> anyhow::Result<Option<Page<T, K>>> {
+        match self.keys.get(key) {
+            Some(page_idx) => Ok(Some(self.pool.get(page_idx)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Flush every dirty page and discard the write-ahead log they now reflect.
+    pub fn checkpoint(&mut self) -> anyhow::Result<()> {
+        self.pool.flush()?;
+        self.heap.sync()?;
+        self.fill.store(&self.dir)?;
+
+        for index in self.indexes.values_mut() {
+            index.store(&self.dir)?;
+        }
+
+        self.keys.sync()?;
+        self.wal.truncate()?;
+        self.last_checkpoint = Instant::now();
+
+        // note: only now can no replayed record still reference these blocks. The
+        // checkpoint itself has succeeded, so every block is freed before reporting
+        let mut stuck = vec![];
+
+        for handle in std::mem::take(&mut self.pending_free) {
+            if let Err(e) = self.heap.free(handle) {
+                stuck.push(format!("{:?} ({:#})", handle, e));
+            }
+        }
+
+        if !stuck.is_empty() {
+            anyhow::bail!(
+                "checkpoint complete, but heap blocks could not be freed: {}",
+                stuck.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Copy the book's files to `dest`, which must not exist. The copy is only
+    /// consistent right after a `checkpoint`, with writers kept out until it returns.
+    pub fn copy_to(&self, dest: &Path) -> anyhow::Result<()> {
+        copy_dir_atomic(&self.dir, dest)
+            .map_err(|e| e.context(format!("failed to copy book {:?}", self.id)))
+    }
+
+    /// Store a variable-length payload in the book's heap.
+    pub fn put_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<HeapRef> {
+        let sync = self.sync_on_commit();
+        self.heap.alloc(bytes, sync)
+    }
+
+    pub fn get_bytes(&self, handle: impl Into<HeapRef>) -> anyhow::Result<Vec<u8>> {
+        self.heap.read(handle.into())
+    }
+
+    pub fn put_str(&mut self, val: &str) -> anyhow::Result<HeapStr> {
+        Ok(HeapStr {
+            raw: self.put_bytes(val.as_bytes())?,
+        })
+    }
+
+    pub fn get_str(&self, handle: HeapStr) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.get_bytes(handle)?)?)
+    }
+
+    pub fn put_vec<V: Persistable>(&mut self, vals: &[V]) -> anyhow::Result<HeapVec<V>> {
+        Ok(HeapVec::new(self.put_bytes(slice_bytes(vals))?))
     }
 
-    pub fn has_key(&self, key: Key) -> bool {
-        self.key_lookup.contains_key(&key)
+    pub fn get_vec<V: Persistable>(&self, handle: HeapVec<V>) -> anyhow::Result<Vec<V>> {
+        bytes_to_vec(&self.get_bytes(handle)?)
     }
 
-    pub fn insert(&mut self, key: Key, val: T) -> anyhow::Result<Option<T>> {
+    /// Release a heap payload. The block is reused only after the next checkpoint.
+    pub fn free(&mut self, handle: impl Into<HeapRef>) -> anyhow::Result<()> {
+        let handle = handle.into();
+
+        if self.pending_free.contains(&handle) {
+            anyhow::bail!("heap handle {:?} is already freed", handle);
+        }
+
+        self.heap.check(handle)?;
+        self.pending_free.push(handle);
+
+        Ok(())
+    }
+
+    /// Start writing dirty pages back without waiting. The log is kept until a
+    /// later `checkpoint` confirms the pages are durable.
+    pub fn flush_async(&self) -> anyhow::Result<()> {
+        self.pool.flush_async()
+    }
+
+    pub fn insert(&mut self, key: K, val: T) -> anyhow::Result<Option<T>> {
         if self.has_key(key) {
             anyhow::bail!("key already exists");
         }
 
-        let page_idx = if let Some(page_idx) = self.partial.iter().next() {
-            *page_idx
-        } else {
-            let page_idx = Idx::new(self.pages.len() as u32);
-            let path = DATA_DIR.join(format!("books/{}/pages/{}", self.id.val, page_idx.val));
-
-            let file = match fs::OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(&path)
-            {
-                Ok(file) => file,
-                Err(e) => {
-                    return Err(anyhow::anyhow!(e).context(format!("failed to open {:?}", path)))
+        self.commit(WalRecord::Insert { key, val })
+    }
+
+    pub fn delete(&mut self, key: K) -> anyhow::Result<()> {
+        if !self.has_key(key) {
+            anyhow::bail!("key not found")
+        }
+
+        self.commit(WalRecord::Delete { key })?;
+
+        Ok(())
+    }
+
+    /// Insert many entries under one log record, filling each page in turn while
+    /// holding its lock once. Items whose key exists or that a unique index
+    /// rejects are skipped; the outcome of each item is returned in order.
+    pub fn insert_many(
+        &mut self,
+        items: impl IntoIterator<Item = (K, T)>,
+    ) -> anyhow::Result<Vec<BatchOutcome<K>>> {
+        self.check_registered()?;
+
+        let (outcomes, accepted) = self.plan_inserts(items);
+
+        if accepted.is_empty() {
+            return Ok(outcomes);
+        }
+
+        self.wal.append_batch(&accepted, self.sync_on_commit())?;
+
+        if let Err(e) = self.apply_inserts(&accepted) {
+            let undo = accepted.iter().rev().map(|record| (record.key(), None));
+
+            return Err(self.roll_back(e, "batch insert", undo));
+        }
+
+        for record in &accepted {
+            self.changes.publish(record.key(), None, record.val());
+        }
+
+        self.after_commit()?;
+
+        Ok(outcomes)
+    }
+
+    /// What `insert_many` would do with each item, without writing anything.
+    pub fn check_insert_many(
+        &self,
+        items: impl IntoIterator<Item = (K, T)>,
+    ) -> Vec<BatchOutcome<K>> {
+        self.plan_inserts(items).0
+    }
+
+    /// The outcome of each item of a batch insert, and the records of those accepted.
+    fn plan_inserts(
+        &self,
+        items: impl IntoIterator<Item = (K, T)>,
+    ) -> (Vec<BatchOutcome<K>>, Vec<WalRecord<T, K>>) {
+        let mut outcomes = vec![];
+        let mut accepted = vec![];
+        let mut seen = HashSet::new();
+
+        let unique = self
+            .indexes
+            .values()
+            .filter(|index| index.is_unique())
+            .collect::<Vec<_>>();
+        let mut claimed = vec![HashMap::new(); unique.len()];
+
+        'items: for (key, val) in items {
+            if self.has_key(key) || seen.contains(&key) {
+                outcomes.push(BatchOutcome::KeyExists);
+                continue;
+            }
+
+            let mut values = Vec::with_capacity(unique.len());
+
+            for (index, claimed) in unique.iter().zip(&claimed) {
+                match index.check_insert(key, &val, claimed) {
+                    Ok(value) => values.push(value),
+                    Err(violation) => {
+                        outcomes.push(BatchOutcome::Violation(violation));
+                        continue 'items;
+                    }
                 }
-            };
+            }
 
-            let page = match Page::new(&file) {
-                Ok(page) => page,
-                Err(e) => {
-                    return Err(
-                        anyhow::anyhow!(e).context(format!("failed to create page {:?}", path))
-                    )
+            for (claimed, value) in claimed.iter_mut().zip(values) {
+                claimed.insert(value, key);
+            }
+
+            seen.insert(key);
+            outcomes.push(BatchOutcome::Applied);
+            accepted.push(WalRecord::Insert { key, val });
+        }
+
+        (outcomes, accepted)
+    }
+
+    /// Delete many keys under one log record, locking each page they live in
+    /// once. The outcome of each key is returned in order.
+    pub fn delete_many(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> anyhow::Result<Vec<BatchOutcome<K>>> {
+        self.check_registered()?;
+
+        let mut outcomes = vec![];
+        let mut by_page = BTreeMap::<Idx, Vec<K>>::new();
+        let mut seen = HashSet::new();
+
+        for key in keys {
+            match self.keys.get(key) {
+                Some(page_idx) if !seen.contains(&key) => {
+                    seen.insert(key);
+                    by_page.entry(page_idx).or_default().push(key);
+                    outcomes.push(BatchOutcome::Applied);
                 }
-            };
+                _ => outcomes.push(BatchOutcome::KeyNotFound),
+            }
+        }
+
+        if by_page.is_empty() {
+            return Ok(outcomes);
+        }
+
+        let records = by_page
+            .values()
+            .flatten()
+            .map(|&key| WalRecord::Delete { key })
+            .collect::<Vec<WalRecord<T, K>>>();
+        self.wal.append_batch(&records, self.sync_on_commit())?;
+
+        let mut removed = Vec::with_capacity(records.len());
+
+        if let Err(e) = self.apply_deletes(&by_page, &mut removed) {
+            let undo = removed.into_iter().rev().map(|(key, val)| (key, Some(val)));
 
-            self.pages.push(page);
-            self.partial.insert(page_idx);
+            return Err(self.roll_back(e, "batch delete", undo));
+        }
+
+        for (key, old) in removed {
+            self.changes.publish(key, Some(old), None);
+        }
+
+        self.after_commit()?;
+
+        Ok(outcomes)
+    }
+
+    /// Log `record` ahead of applying it to the pages, then sync as the policy requires.
+    fn commit(&mut self, record: WalRecord<T, K>) -> anyhow::Result<Option<T>> {
+        self.check_registered()?;
+        self.check_constraints(std::slice::from_ref(&record))?;
+
+        let (key, new) = (record.key(), record.val());
+        let old = self.get(key)?;
+
+        self.wal.append(&record, self.sync_on_commit())?;
+
+        let ret = match self.apply(record) {
+            Ok(ret) => ret,
+            Err(e) => return Err(self.roll_back(e, "write", [(key, old)])),
+        };
+
+        self.changes.publish(key, ret, new);
+        self.after_commit()?;
+
+        Ok(ret)
+    }
+
+    #[inline]
+    fn sync_on_commit(&self) -> bool {
+        matches!(
+            self.options.sync_policy,
+            SyncPolicy::EveryWrite | SyncPolicy::OnCommit
+        )
+    }
+
+    fn after_commit(&mut self) -> anyhow::Result<()> {
+        let checkpoint_due = match self.options.sync_policy {
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::Interval(interval) => self.last_checkpoint.elapsed() >= interval,
+            SyncPolicy::Never | SyncPolicy::OnCommit => false,
+        };
+
+        if checkpoint_due || self.wal.len() > WAL_CHECKPOINT_BYTES {
+            self.checkpoint()?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply `record` to the pages, returning the value it displaced. Idempotent,
+    /// so the WAL can be replayed over pages that already contain some or all of its effects.
+    fn apply(&mut self, record: WalRecord<T, K>) -> anyhow::Result<Option<T>> {
+        // note: the index must be marked unclean before any page it describes changes
+        self.keys.mark_dirty()?;
+        self.forget_dormant_indexes()?;
+
+        match record {
+            WalRecord::Insert { key, val } | WalRecord::Replace { key, val } => {
+                self.apply_put(key, val)
+            }
+            WalRecord::Delete { key } => self.apply_delete(key),
+        }
+    }
+
+    fn apply_put(&mut self, key: K, val: T) -> anyhow::Result<Option<T>> {
+        if let Some(page_idx) = self.keys.get(key) {
+            let page = self.pool.get(page_idx)?;
+            self.pool.mark_dirty(page_idx);
+
+            let old = page.write().insert(key, val)?;
+
+            for index in self.indexes.values_mut() {
+                if let Some(old) = &old {
+                    index.remove(key, old);
+                }
+
+                index.insert(key, &val);
+            }
+
+            return Ok(old);
+        }
+
+        let partial = self.fill.partial().next();
+        let page_idx = if let Some(page_idx) = partial {
             page_idx
+        } else {
+            self.alloc_page()?
         };
 
-        self.key_lookup.insert(key, page_idx);
+        let page = self.pool.get(page_idx)?;
+        self.pool.mark_dirty(page_idx);
 
-        let page = &self.pages[page_idx.as_usize()];
         let ret = { page.write().insert(key, val)? };
 
-        if page.read().is_full() {
-            self.partial.remove(&page_idx);
+        if let Err(e) = self.keys.insert(key, page_idx) {
+            // note: an entry the key lookup does not know about could never be undone
+            page.write().delete(key)?;
+            return Err(e);
+        }
+
+        self.fill.adjust(page_idx, true);
+
+        for index in self.indexes.values_mut() {
+            index.insert(key, &val);
         }
 
         Ok(ret)
     }
 
-    pub fn delete(&mut self, key: Key) -> anyhow::Result<()> {
-        let page_idx = if let Some(page_idx) = self.key_lookup.get(&key) {
-            *page_idx
+    fn apply_delete(&mut self, key: K) -> anyhow::Result<Option<T>> {
+        let page_idx = if let Some(page_idx) = self.keys.get(key) {
+            page_idx
+        } else {
+            return Ok(None);
+        };
+
+        let page = self.pool.get(page_idx)?;
+        let old = page.read().get(key);
+        page.write().delete(key)?;
+
+        self.keys.remove(key)?;
+        self.pool.mark_dirty(page_idx);
+        self.fill.adjust(page_idx, false);
+
+        if let Some(old) = old {
+            for index in self.indexes.values_mut() {
+                index.remove(key, &old);
+            }
+        }
+
+        Ok(old)
+    }
+
+    /// Apply inserts of keys not yet in the book, filling partial pages first.
+    /// Each entry is registered as soon as it is written, so a failure leaves
+    /// the book consistent with the entries written so far.
+    fn apply_inserts(&mut self, records: &[WalRecord<T, K>]) -> anyhow::Result<()> {
+        self.keys.mark_dirty()?;
+        self.forget_dormant_indexes()?;
+
+        let cap = self.fill.cap();
+        let mut rest = records;
+
+        while !rest.is_empty() {
+            let partial = self.fill.partial().next();
+            let page_idx = if let Some(page_idx) = partial {
+                page_idx
+            } else {
+                self.alloc_page()?
+            };
+
+            let room = cap - self.fill.len_of(page_idx).unwrap_or_default();
+            let (chunk, tail) = rest.split_at(room.min(rest.len()));
+            rest = tail;
+
+            let page = self.pool.get(page_idx)?;
+            self.pool.mark_dirty(page_idx);
+
+            let mut page_guard = page.write();
+
+            for record in chunk {
+                let (key, val) = match record {
+                    WalRecord::Insert { key, val } | WalRecord::Replace { key, val } => {
+                        (*key, *val)
+                    }
+                    WalRecord::Delete { .. } => anyhow::bail!("cannot insert a delete record"),
+                };
+
+                page_guard.insert(key, val)?;
+
+                self.keys.insert(key, page_idx)?;
+                self.fill.adjust(page_idx, true);
+
+                for index in self.indexes.values_mut() {
+                    index.insert(key, &val);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply deletes grouped by the page holding each key, recording every
+    /// removed entry in `removed` as it goes.
+    fn apply_deletes(
+        &mut self,
+        by_page: &BTreeMap<Idx, Vec<K>>,
+        removed: &mut Vec<(K, T)>,
+    ) -> anyhow::Result<()> {
+        self.keys.mark_dirty()?;
+        self.forget_dormant_indexes()?;
+
+        for (&page_idx, keys) in by_page {
+            let page = self.pool.get(page_idx)?;
+            self.pool.mark_dirty(page_idx);
+
+            let mut page_guard = page.write();
+
+            for &key in keys {
+                let old = if let Some(old) = page_guard.get(key) {
+                    old
+                } else {
+                    anyhow::bail!(
+                        "key {:?} is indexed in page {} but not stored there",
+                        key,
+                        page_idx
+                    )
+                };
+
+                page_guard.delete(key)?;
+
+                self.keys.remove(key)?;
+                self.fill.adjust(page_idx, false);
+                removed.push((key, old));
+
+                for index in self.indexes.values_mut() {
+                    index.remove(key, &old);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn alloc_page(&mut self) -> anyhow::Result<Idx> {
+        // note: reclaimed indices are reused before the book grows
+        let page_idx = self.fill.first_vacant_idx();
+
+        self.pool.create(page_idx)?;
+        self.fill.set(page_idx, 0);
+
+        Ok(page_idx)
+    }
+
+    pub fn get(&self, key: K) -> anyhow::Result<Option<T>> {
+        match self.page_of(key)? {
+            Some(page) => Ok(page.read().get(key)),
+            None => Ok(None),
+        }
+    }
+
+    /// Entries whose keys fall within `range`, in `K` order. Reverse it to walk
+    /// the range from the top.
+    pub fn range(
+        &self,
+        range: impl RangeBounds<K>,
+    ) -> impl DoubleEndedIterator<Item = anyhow::Result<(K, T)>> + '_ {
+        self.keys.range(range).map(|(key, page_idx)| {
+            let page = self.pool.get(page_idx)?;
+            let val = page.read().get(key);
+
+            match val {
+                Some(val) => Ok((key, val)),
+                None => anyhow::bail!(
+                    "key {:?} is indexed in page {} but not stored there",
+                    key,
+                    page_idx
+                ),
+            }
+        })
+    }
+
+    pub fn update<R>(&mut self, key: K, f: impl FnOnce(&mut T) -> R) -> anyhow::Result<R> {
+        let mut val = if let Some(val) = self.get(key)? {
+            val
         } else {
             anyhow::bail!("key not found")
         };
 
-        let mut page_guard = self.pages[page_idx.as_usize()].read();
+        let ret = f(&mut val);
+        self.commit(WalRecord::Replace { key, val })?;
+
+        Ok(ret)
+    }
+
+    pub fn upsert(&mut self, key: K, val: T) -> anyhow::Result<Option<T>> {
+        if self.has_key(key) {
+            self.commit(WalRecord::Replace { key, val })
+        } else {
+            self.commit(WalRecord::Insert { key, val })
+        }
+    }
+
+    /// Subscribe to the changes committed from now on.
+    pub fn subscribe(&mut self) -> Subscription<T, K> {
+        self.changes
+            .subscribe(None)
+            .expect("the next change is always available")
+    }
+
+    /// Subscribe to the changes committed from `seq` on, e.g. the `resume_from`
+    /// of a `Lagged` subscription. Only the last `change_buffer` changes are
+    /// kept, and sequence numbers start over whenever the book is opened.
+    pub fn subscribe_from(&mut self, seq: u64) -> anyhow::Result<Subscription<T, K>> {
+        self.changes.subscribe(Some(seq))
+    }
+
+    /// Run `f` against a staged view of the book and commit its writes as one unit.
+    ///
+    /// Nothing reaches the book if `f` returns an error. If applying the staged
+    /// writes fails part way, the ones already applied are undone.
+    pub fn transaction<R>(
+        &mut self,
+        f: impl FnOnce(&mut Transaction<'_, T, K>) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let mut tx = Transaction::new(self);
+        let ret = f(&mut tx)?;
+        let records = tx.into_records();
 
-        if page_guard.is_full() {
-            self.partial.insert(page_idx);
+        self.commit_batch(records)?;
+
+        Ok(ret)
+    }
+
+    fn commit_batch(&mut self, records: Vec<WalRecord<T, K>>) -> anyhow::Result<()> {
+        if records.is_empty() {
+            return Ok(());
         }
 
-        page_guard.with_upgraded(|page_guard| page_guard.delete(key))?;
+        self.check_registered()?;
+        self.check_constraints(&records)?;
+
+        // note: read before logging, as seen by each record after the ones before it,
+        // so that nothing can fail between the first write and a rollback
+        let mut staged = HashMap::new();
+        let mut olds = Vec::with_capacity(records.len());
+
+        for record in &records {
+            let old = match staged.get(&record.key()) {
+                Some(old) => *old,
+                None => self.get(record.key())?,
+            };
+
+            staged.insert(record.key(), record.val());
+            olds.push(old);
+        }
+
+        self.wal.append_batch(&records, self.sync_on_commit())?;
+
+        let mut applied = Vec::with_capacity(records.len());
+
+        for (record, old) in records.into_iter().zip(olds) {
+            let (key, new) = (record.key(), record.val());
+
+            if let Err(e) = self.apply(record) {
+                let undo = applied.into_iter().rev().map(|(key, old, _)| (key, old));
+
+                return Err(self.roll_back(
+                    e,
+                    "transaction",
+                    std::iter::once((key, old)).chain(undo),
+                ));
+            }
+
+            applied.push((key, old, new));
+        }
+
+        for (key, old, new) in applied {
+            self.changes.publish(key, old, new);
+        }
+
+        self.after_commit()
+    }
+
+    /// Undo a logged batch whose application failed part way with `e`, given
+    /// the value each written key held before, newest first. The log is dropped
+    /// even if some undo fails, so a batch reported as failed is never replayed.
+    fn roll_back(
+        &mut self,
+        e: anyhow::Error,
+        batch: &str,
+        undo: impl IntoIterator<Item = (K, Option<T>)>,
+    ) -> anyhow::Error {
+        let mut stuck = vec![];
+
+        for (key, old) in undo {
+            let undone = match old {
+                Some(val) => self.apply_put(key, val),
+                None => self.apply_delete(key),
+            };
+
+            if let Err(undo_err) = undone {
+                stuck.push(format!("{:?} ({:#})", key, undo_err));
+            }
+        }
+
+        // note: drop the logged batch so replay cannot resurrect it
+        if let Err(checkpoint_err) = self.checkpoint() {
+            return e.context(format!(
+                "{} failed and could not be dropped from the log: {:#}",
+                batch, checkpoint_err
+            ));
+        }
+
+        if stuck.is_empty() {
+            e.context(format!("{} rolled back", batch))
+        } else {
+            e.context(format!(
+                "{} only partly rolled back, could not restore {}",
+                batch,
+                stuck.join(", ")
+            ))
+        }
+    }
+
+    /// Refuse writes while a unique constraint declared on the book has no index
+    /// enforcing it, as is the case after reopening until it is registered again.
+    fn check_registered(&self) -> anyhow::Result<()> {
+        match self
+            .constraints
+            .iter()
+            .find(|name| !self.indexes.contains_key(*name))
+        {
+            Some(name) => anyhow::bail!(
+                "unique constraint {:?} must be registered with `add_unique_constraint` before writing",
+                name
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Reject `records` before they are logged if, applied together, they would
+    /// violate a unique index. The error wraps a `ConstraintViolation`.
+    fn check_constraints(&self, records: &[WalRecord<T, K>]) -> anyhow::Result<()> {
+        if !self.indexes.values().any(SecondaryIndex::is_unique) {
+            return Ok(());
+        }
+
+        let mut writes = BTreeMap::new();
+
+        for record in records {
+            let val = match record {
+                WalRecord::Insert { val, .. } | WalRecord::Replace { val, .. } => Some(*val),
+                WalRecord::Delete { .. } => None,
+            };
+
+            writes.insert(record.key(), val);
+        }
+
+        for index in self.indexes.values() {
+            index.check(&writes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Register a secondary index over the values `extractor` derives from each
+    /// entry. An index stored under `name` while the book was last open is reused
+    /// if nothing changed since; otherwise it is built from the pages.
+    ///
+    /// A unique index is a constraint: it fails to build over entries that share
+    /// a value, and from then on rejects with a `ConstraintViolation` any write
+    /// that would make two entries share one.
+    pub fn create_index(
+        &mut self,
+        name: &str,
+        extractor: impl Fn(&T) -> IndexKey + Send + Sync + 'static,
+        unique: bool,
+    ) -> anyhow::Result<()> {
+        SecondaryIndex::<T>::validate_name(name)?;
+
+        if self.indexes.contains_key(name) {
+            anyhow::bail!("index {:?} already exists", name);
+        }
+
+        if !unique && self.constraints.contains(name) {
+            anyhow::bail!(
+                "index {:?} backs a unique constraint and must be unique",
+                name
+            );
+        }
+
+        let extractor: IndexExtractor<T> = Arc::new(extractor);
+
+        let stored = if self.dormant.remove(name) {
+            SecondaryIndex::load(&self.dir, name, unique, extractor.clone())?
+        } else {
+            None
+        };
+
+        let index = match stored {
+            Some(index) => index,
+            None => self.build_index(SecondaryIndex::new(name, unique, extractor))?,
+        };
+
+        self.indexes.insert(name.to_string(), index);
+
+        Ok(())
+    }
+
+    /// Declare that no two entries may share the value `extractor` derives from
+    /// them, backed by a unique index called `name`.
+    ///
+    /// The declaration is recorded in the book's manifest. Extractors cannot be
+    /// stored, so once the book is reopened it rejects every write until each
+    /// declared constraint is added again; `drop_index` retracts one.
+    pub fn add_unique_constraint(
+        &mut self,
+        name: &str,
+        extractor: impl Fn(&T) -> IndexKey + Send + Sync + 'static,
+    ) -> anyhow::Result<()> {
+        self.create_index(name, extractor, true)?;
+
+        if self.constraints.insert(name.to_string()) {
+            if let Err(e) = self.store_constraints() {
+                self.constraints.remove(name);
+                return Err(e);
+            }
+        }
 
         Ok(())
     }
+
+    /// Names of the unique constraints declared on the book, registered or not.
+    pub fn unique_constraints(&self) -> impl Iterator<Item = &str> + '_ {
+        self.constraints.iter().map(String::as_str)
+    }
+
+    fn store_constraints(&self) -> anyhow::Result<()> {
+        let mut manifest =
+            BookManifest::load(&self.dir)?.unwrap_or_else(|| BookManifest::new(&self.options));
+        manifest.unique_constraints = self.constraints.clone();
+
+        manifest.store(&self.dir)
+    }
+
+    /// Throw away the entries of the index `name` and read them back from the pages.
+    pub fn rebuild_index(&mut self, name: &str) -> anyhow::Result<()> {
+        let index = if let Some(index) = self.indexes.remove(name) {
+            index
+        } else {
+            anyhow::bail!("index {:?} not found", name)
+        };
+
+        let rebuilt = SecondaryIndex::new(name, index.is_unique(), index.extractor());
+
+        match self.build_index(rebuilt) {
+            Ok(rebuilt) => {
+                self.indexes.insert(name.to_string(), rebuilt);
+                Ok(())
+            }
+            Err(e) => {
+                self.indexes.insert(name.to_string(), index);
+                Err(e)
+            }
+        }
+    }
+
+    /// Unregister the index `name` and delete its stored entries, retracting
+    /// the unique constraint it backs, if any.
+    pub fn drop_index(&mut self, name: &str) -> anyhow::Result<()> {
+        // note: a declared constraint can be retracted without registering it first
+        if !self.indexes.contains_key(name) && !self.constraints.contains(name) {
+            anyhow::bail!("index {:?} not found", name);
+        }
+
+        if self.constraints.remove(name) {
+            if let Err(e) = self.store_constraints() {
+                self.constraints.insert(name.to_string());
+                return Err(e);
+            }
+        }
+
+        self.indexes.remove(name);
+        self.dormant.remove(name);
+
+        let path = SecondaryIndex::<T>::path(&self.dir, name);
+
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow::anyhow!(e).context(format!("failed to remove {:?}", path))),
+        }
+    }
+
+    /// Names of the registered indexes.
+    pub fn index_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.indexes.keys().map(String::as_str)
+    }
+
+    /// Entries whose `name` index value equals `value`, in `K` order.
+    pub fn lookup_by_index(
+        &self,
+        name: &str,
+        value: impl Into<IndexKey>,
+    ) -> anyhow::Result<Vec<(K, T)>> {
+        let index = self.index(name)?;
+        let value = value.into();
+
+        index
+            .get(&value)
+            .map(|key| self.indexed_entry(index, key))
+            .collect()
+    }
+
+    /// Entries whose `name` index value falls within `range`, ordered by that value.
+    pub fn index_range(
+        &self,
+        name: &str,
+        range: impl RangeBounds<IndexKey>,
+    ) -> anyhow::Result<Vec<(K, T)>> {
+        let index = self.index(name)?;
+
+        index
+            .range(range)
+            .map(|(_, key)| self.indexed_entry(index, key))
+            .collect()
+    }
+
+    fn index(&self, name: &str) -> anyhow::Result<&SecondaryIndex<T, K>> {
+        match self.indexes.get(name) {
+            Some(index) => Ok(index),
+            None => anyhow::bail!("index {:?} not found", name),
+        }
+    }
+
+    fn indexed_entry(&self, index: &SecondaryIndex<T, K>, key: K) -> anyhow::Result<(K, T)> {
+        match self.get(key)? {
+            Some(val) => Ok((key, val)),
+            None => anyhow::bail!("index {:?} refers to missing key {:?}", index.name(), key),
+        }
+    }
+
+    fn build_index(&self, mut index: SecondaryIndex<T, K>) -> anyhow::Result<SecondaryIndex<T, K>> {
+        for page_idx in self.fill.pages() {
+            let page = self.pool.get(page_idx)?;
+            let page_guard = page.read();
+
+            for key in page_guard.keys() {
+                let val = page_guard.get(*key).expect("key is in the page");
+
+                index
+                    .check(&BTreeMap::from([(*key, Some(val))]))
+                    .map_err(|e| {
+                        anyhow::anyhow!(e)
+                            .context(format!("failed to build unique index {:?}", index.name()))
+                    })?;
+
+                index.insert(*key, &val);
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Stored indexes that were not registered again before the first write
+    /// would miss it, so they are deleted and have to be rebuilt.
+    fn forget_dormant_indexes(&mut self) -> anyhow::Result<()> {
+        for name in std::mem::take(&mut self.dormant) {
+            let path = SecondaryIndex::<T>::path(&self.dir, &name);
+
+            fs::remove_file(&path)
+                .map_err(|e| anyhow::anyhow!(e).context(format!("failed to remove {:?}", path)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Empty the least-filled partial page into the fullest ones and delete its file.
+    ///
+    /// Returns `None` once no page can be reclaimed. Every step is logged and
+    /// checkpointed on its own, so callers can release the book between steps.
+    /// Relocated entries change position, so running cursors may skip or repeat them.
+    pub fn compact_step(&mut self) -> anyhow::Result<Option<CompactionStep>> {
+        if self.page_count() <= 1 {
+            return Ok(None);
+        }
+
+        let mut candidates = self
+            .fill
+            .partial()
+            .map(|page_idx| (self.fill.len_of(page_idx).unwrap_or_default(), page_idx))
+            .collect::<Vec<_>>();
+
+        // note: sparsest first, and later pages first among equals so the book shrinks
+        candidates.sort_unstable_by_key(|(len, page_idx)| (*len, Reverse(*page_idx)));
+
+        let (source_len, source) = if let Some(candidate) = candidates.first() {
+            *candidate
+        } else {
+            return Ok(None);
+        };
+
+        let cap = self.fill.cap();
+        let room: usize = candidates[1..].iter().map(|(len, _)| cap - len).sum();
+
+        if source_len > room {
+            return Ok(None);
+        }
+
+        let source_page = self.pool.get(source)?;
+        let entries = {
+            let source_guard = source_page.read();
+            source_guard
+                .keys()
+                .map(|key| (*key, source_guard.get(*key).expect("key is in the page")))
+                .collect::<Vec<_>>()
+        };
+
+        let mut targets = candidates[1..]
+            .iter()
+            .rev()
+            .flat_map(|(len, page_idx)| std::iter::repeat_n(*page_idx, cap - len));
+        let moves = entries
+            .into_iter()
+            .map(|(key, val)| (key, val, targets.next().expect("there is room")))
+            .collect::<Vec<_>>();
+
+        let records = moves
+            .iter()
+            .flat_map(|(key, val, _)| {
+                [
+                    WalRecord::Delete { key: *key },
+                    WalRecord::Insert {
+                        key: *key,
+                        val: *val,
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        // note: always synced, since the source page may reach disk before its targets
+        if !records.is_empty() {
+            self.wal.append_batch(&records, true)?;
+        }
+
+        self.keys.mark_dirty()?;
+
+        self.pool.mark_dirty(source);
+
+        for (key, val, target) in &moves {
+            if let Err(e) = self.move_entry(&source_page, source, *key, *val, *target) {
+                drop(source_page);
+
+                // note: the moves made so far are complete, so a checkpoint keeps them
+                // and drops the rest of the logged batch, which replay would redo
+                if let Err(checkpoint_err) = self.checkpoint() {
+                    return Err(e.context(format!(
+                        "compaction failed and could not be dropped from the log: {:#}",
+                        checkpoint_err
+                    )));
+                }
+
+                return Err(e.context(format!("compaction of page {} stopped partway", source)));
+            }
+        }
+
+        drop(source_page);
+
+        self.checkpoint()?;
+        self.reclaim_page(source)?;
+
+        Ok(Some(CompactionStep {
+            reclaimed: source,
+            relocated: moves.len(),
+        }))
+    }
+
+    /// Move one entry of `source` to `target` during compaction. A failure leaves
+    /// the entry on `source` only, as it was.
+    fn move_entry(
+        &mut self,
+        source_page: &Page<T, K>,
+        source: Idx,
+        key: K,
+        val: T,
+        target: Idx,
+    ) -> anyhow::Result<()> {
+        let target_page = self.pool.get(target)?;
+
+        // note: dirty before written, so the change is never evicted unflushed
+        self.pool.mark_dirty(target);
+        target_page.write().insert(key, val)?;
+
+        if let Err(e) = source_page.write().delete(key) {
+            if let Err(undo_err) = target_page.write().delete(key) {
+                return Err(e.context(format!(
+                    "{:?} is left on both pages {} and {}: {:#}",
+                    key, source, target, undo_err
+                )));
+            }
+
+            return Err(e);
+        }
+
+        self.keys.insert(key, target)?;
+        self.fill.adjust(target, true);
+        self.fill.adjust(source, false);
+
+        Ok(())
+    }
+
+    /// Run `compact_step` until nothing more can be reclaimed, returning the steps taken.
+    pub fn compact(&mut self) -> anyhow::Result<Vec<CompactionStep>> {
+        let mut steps = vec![];
+
+        while let Some(step) = self.compact_step()? {
+            steps.push(step);
+        }
+
+        Ok(steps)
+    }
+
+    /// Check every page against its checksum, its in-memory metadata and the key
+    /// lookup. Checksums of dirty pages are skipped, since they are only restamped
+    /// on flush; `Book::verify` checkpoints first so that none are.
+    pub fn verify(&self) -> VerifyReport<K> {
+        let mut report = VerifyReport::default();
+        let mut stored_in = HashMap::<K, Vec<Idx>>::with_capacity(self.keys.len());
+
+        for page_idx in self.fill.pages() {
+            report.pages_checked += 1;
+
+            let checked = self.pool.get(page_idx).and_then(|page| {
+                let page_guard = page.read();
+
+                Ok((
+                    page_guard.checksums()?,
+                    page_guard.meta_mismatches()?,
+                    page_guard.parse_meta()?,
+                ))
+            });
+
+            let ((stored, computed), mismatches, parsed) = match checked {
+                Ok(checked) => checked,
+                Err(e) => {
+                    report.problems.push(Problem::Unreadable {
+                        page: page_idx,
+                        error: format!("{:#}", e),
+                    });
+                    continue;
+                }
+            };
+
+            if stored != computed && !self.pool.is_dirty(page_idx) {
+                report.problems.push(Problem::ChecksumMismatch {
+                    page: page_idx,
+                    stored,
+                    computed,
+                });
+            }
+
+            report
+                .problems
+                .extend(mismatches.into_iter().map(|(slot, on_page, in_memory)| {
+                    Problem::BitmapMismatch {
+                        page: page_idx,
+                        slot,
+                        on_page,
+                        in_memory,
+                    }
+                }));
+
+            for key in parsed.keys() {
+                report.entries_checked += 1;
+                stored_in.entry(*key).or_default().push(page_idx);
+
+                if self.keys.get(*key) != Some(page_idx) {
+                    report.problems.push(Problem::UnindexedKey {
+                        key: *key,
+                        page: page_idx,
+                    });
+                }
+            }
+        }
+
+        for (key, page_idx) in self.keys.iter() {
+            if !stored_in
+                .get(&key)
+                .is_some_and(|pages| pages.contains(&page_idx))
+            {
+                report.problems.push(Problem::DanglingLookup {
+                    key,
+                    page: page_idx,
+                });
+            }
+        }
+
+        let mut duplicates = stored_in
+            .into_iter()
+            .filter(|(_, pages)| pages.len() > 1)
+            .collect::<Vec<_>>();
+        duplicates.sort_unstable_by_key(|(key, _)| *key);
+
+        report.problems.extend(
+            duplicates
+                .into_iter()
+                .map(|(key, pages)| Problem::DuplicateKey { key, pages }),
+        );
+
+        report
+    }
+
+    /// Tombstone an empty page and delete its file. Its index may be reused later.
+    fn reclaim_page(&mut self, page_idx: Idx) -> anyhow::Result<()> {
+        debug_assert_eq!(self.fill.len_of(page_idx), Some(0));
+
+        self.fill.remove(page_idx);
+        self.pool.remove(page_idx);
+
+        let path = self.pool.path(page_idx);
+
+        fs::remove_file(&path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to remove {:?}", path)))?;
+
+        Ok(())
+    }
+}
+
+impl<T: Persistable, K: BookKey> Drop for BookInner<T, K> {
+    fn drop(&mut self) {
+        if let Err(e) = self.checkpoint() {
+            eprintln!("failed to checkpoint book {:?} on drop: {:?}", self.id, e);
+        }
+    }
 }
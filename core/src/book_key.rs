@@ -0,0 +1,67 @@
+use std::{
+    fmt::Debug,
+    hash::Hash,
+    mem::{size_of, MaybeUninit},
+};
+
+use crate::{persistable::Persistable, Key};
+
+/// A type that can key the entries of a book.
+///
+/// Keys are stored bytewise in pages, the write-ahead log and the key index,
+/// and kept in `Ord` order by the latter. `Key` is the default; wider keys such
+/// as `u64`, `u128` (e.g. a UUID) or a fixed-size byte array make collisions
+/// unlikely and let callers use natural keys.
+pub trait BookKey: Persistable + Ord + Hash + Debug {
+    /// The lowest key, bounding range scans from below.
+    const MIN: Self;
+    /// The highest key, bounding range scans from above.
+    const MAX: Self;
+
+    #[inline]
+    fn to_bytes(&self, buf: &mut Vec<u8>) {
+        // note: `Persistable` guarantees the value is plain bytes without padding
+        let bytes = unsafe {
+            std::slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>())
+        };
+        buf.extend_from_slice(bytes);
+    }
+
+    /// Read a key back from the first `size_of::<Self>()` bytes of `bytes`.
+    #[inline]
+    fn from_bytes(bytes: &[u8]) -> Self {
+        assert!(bytes.len() >= size_of::<Self>(), "key is truncated");
+
+        let mut key = MaybeUninit::<Self>::uninit();
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                key.as_mut_ptr() as *mut u8,
+                size_of::<Self>(),
+            );
+
+            key.assume_init()
+        }
+    }
+}
+
+impl BookKey for Key {
+    const MIN: Self = Key { val: u32::MIN };
+    const MAX: Self = Key { val: u32::MAX };
+}
+
+impl BookKey for u64 {
+    const MIN: Self = u64::MIN;
+    const MAX: Self = u64::MAX;
+}
+
+impl BookKey for u128 {
+    const MIN: Self = u128::MIN;
+    const MAX: Self = u128::MAX;
+}
+
+impl<const N: usize> BookKey for [u8; N] {
+    const MIN: Self = [u8::MIN; N];
+    const MAX: Self = [u8::MAX; N];
+}
@@ -0,0 +1,264 @@
+#[cfg(test)]
+use std::cell::Cell;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use parking_lot::Mutex;
+
+use crate::{book_key::BookKey, page::Page, persistable::Persistable, Idx, Key};
+
+#[cfg(test)]
+thread_local! {
+    /// Makes the next flush of an evicted page fail, to exercise its error path.
+    pub(crate) static FAIL_EVICTION_FLUSH: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Page cache counters, as reported by `BufferPool::stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub resident: usize,
+}
+
+#[derive(Debug)]
+struct Frame<T: Persistable, K: BookKey = Key> {
+    page_idx: Idx,
+    page: Page<T, K>,
+    referenced: bool,
+}
+
+#[derive(Debug)]
+struct PoolState<T: Persistable, K: BookKey = Key> {
+    frames: Vec<Frame<T, K>>,
+    slots: HashMap<Idx, usize>,
+    hand: usize,
+    dirty: BTreeSet<Idx>,
+}
+
+/// Bounded cache of mapped pages, loaded on first access and evicted with CLOCK.
+///
+/// A page handed out by the pool stays pinned for as long as the caller holds
+/// it (or a guard on it) and is never evicted in the meantime. If every frame
+/// is pinned the pool grows past its capacity rather than fail. Dirty pages are
+/// flushed when they are evicted.
+#[derive(Debug)]
+pub struct BufferPool<T: Persistable, K: BookKey = Key> {
+    pages_dir: PathBuf,
+    page_size: usize,
+    capacity: usize,
+    state: Mutex<PoolState<T, K>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<T: Persistable, K: BookKey> BufferPool<T, K> {
+    pub fn new(pages_dir: &Path, page_size: usize, capacity: usize) -> Self {
+        BufferPool {
+            pages_dir: pages_dir.to_path_buf(),
+            page_size,
+            capacity: capacity.max(1),
+            state: Mutex::new(PoolState {
+                frames: vec![],
+                slots: HashMap::new(),
+                hand: 0,
+                dirty: BTreeSet::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn path(&self, page_idx: Idx) -> PathBuf {
+        self.pages_dir.join(page_idx.val.to_string())
+    }
+
+    /// The page stored under `page_idx`, mapping it if it is not resident.
+    pub fn get(&self, page_idx: Idx) -> anyhow::Result<Page<T, K>> {
+        let mut state = self.state.lock();
+
+        if let Some(slot) = state.slots.get(&page_idx).copied() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+
+            let frame = &mut state.frames[slot];
+            frame.referenced = true;
+
+            return Ok(frame.page.clone());
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let page = Self::map(&self.path(page_idx), self.page_size, false)?;
+        self.admit(&mut state, page_idx, page.clone())?;
+
+        Ok(page)
+    }
+
+    /// Create an empty page file for `page_idx` and cache it.
+    pub fn create(&self, page_idx: Idx) -> anyhow::Result<Page<T, K>> {
+        let mut state = self.state.lock();
+
+        let page = Self::map(&self.path(page_idx), self.page_size, true)?;
+        self.admit(&mut state, page_idx, page.clone())?;
+
+        Ok(page)
+    }
+
+    /// Map a page file directly, bypassing the cache.
+    pub fn map(path: &Path, page_size: usize, create: bool) -> anyhow::Result<Page<T, K>> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to open {:?}", path)))?;
+
+        if file.metadata()?.len() == 0 {
+            Page::new(&file, page_size)
+                .map_err(|e| e.context(format!("failed to create page {:?}", path)))
+        } else {
+            Page::parse(&file, page_size)
+                .map_err(|e| e.context(format!("failed to parse page {:?}", path)))
+        }
+    }
+
+    fn admit(
+        &self,
+        state: &mut PoolState<T, K>,
+        page_idx: Idx,
+        page: Page<T, K>,
+    ) -> anyhow::Result<()> {
+        if state.frames.len() >= self.capacity {
+            self.evict_one(state)?;
+        }
+
+        state.slots.insert(page_idx, state.frames.len());
+        state.frames.push(Frame {
+            page_idx,
+            page,
+            referenced: true,
+        });
+
+        Ok(())
+    }
+
+    /// Sweep the clock hand until an unpinned, unreferenced frame turns up.
+    fn evict_one(&self, state: &mut PoolState<T, K>) -> anyhow::Result<bool> {
+        for _ in 0..2 * state.frames.len() {
+            let slot = state.hand % state.frames.len();
+            let frame = &mut state.frames[slot];
+
+            if frame.page.is_pinned() || frame.referenced {
+                frame.referenced = false;
+                state.hand = slot + 1;
+                continue;
+            }
+
+            let page_idx = frame.page_idx;
+
+            // note: nobody else holds the page, so taking its lock cannot block. It
+            // stays dirty until the flush succeeds, or a checkpoint would skip it
+            if state.dirty.contains(&page_idx) {
+                Self::flush_evicted(&frame.page)?;
+                state.dirty.remove(&page_idx);
+            }
+
+            state.frames.swap_remove(slot);
+            state.slots.remove(&page_idx);
+
+            if let Some(moved) = state.frames.get(slot) {
+                state.slots.insert(moved.page_idx, slot);
+            }
+
+            state.hand = slot;
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn flush_evicted(page: &Page<T, K>) -> anyhow::Result<()> {
+        #[cfg(test)]
+        if FAIL_EVICTION_FLUSH.with(|fail| fail.replace(false)) {
+            anyhow::bail!("injected eviction flush failure");
+        }
+
+        page.write().flush()
+    }
+
+    /// Remember that `page_idx` must be flushed before it is evicted. The page
+    /// must be resident, which it is for as long as the caller holds it.
+    pub fn mark_dirty(&self, page_idx: Idx) {
+        self.state.lock().dirty.insert(page_idx);
+    }
+
+    pub fn is_dirty(&self, page_idx: Idx) -> bool {
+        self.state.lock().dirty.contains(&page_idx)
+    }
+
+    fn dirty_pages(&self) -> Vec<(Idx, Page<T, K>)> {
+        let state = self.state.lock();
+
+        state
+            .dirty
+            .iter()
+            .map(|page_idx| {
+                let slot = state.slots[page_idx];
+                (*page_idx, state.frames[slot].page.clone())
+            })
+            .collect()
+    }
+
+    /// Flush every dirty page.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        for (page_idx, page) in self.dirty_pages() {
+            page.write().flush()?;
+            self.state.lock().dirty.remove(&page_idx);
+        }
+
+        Ok(())
+    }
+
+    /// Start writing dirty pages back without waiting; they stay dirty.
+    pub fn flush_async(&self) -> anyhow::Result<()> {
+        for (_, page) in self.dirty_pages() {
+            page.read().flush_async()?;
+        }
+
+        Ok(())
+    }
+
+    /// Forget `page_idx`, e.g. once its file has been deleted. Holders of the page keep it.
+    pub fn remove(&self, page_idx: Idx) {
+        let mut state = self.state.lock();
+        state.dirty.remove(&page_idx);
+
+        if let Some(slot) = state.slots.remove(&page_idx) {
+            state.frames.swap_remove(slot);
+
+            if let Some(moved) = state.frames.get(slot).map(|frame| frame.page_idx) {
+                state.slots.insert(moved, slot);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            resident: self.state.lock().frames.len(),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::{book_key::BookKey, persistable::Persistable, Key};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    Insert,
+    Replace,
+    Delete,
+}
+
+/// A committed change to one entry of a book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChangeEvent<T, K = Key> {
+    /// Position of the change in the book's feed, counted from when it was opened.
+    pub seq: u64,
+    pub key: K,
+    pub kind: ChangeKind,
+    pub old: Option<T>,
+    pub new: Option<T>,
+}
+
+/// The last item of a subscription that fell more than a buffer behind. Changes
+/// from `resume_from` on were not delivered; pass it to `Book::subscribe_from`
+/// to pick up where the subscription stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lagged {
+    pub resume_from: u64,
+}
+
+impl fmt::Display for Lagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "subscriber fell behind, changes from {} on were dropped",
+            self.resume_from
+        )
+    }
+}
+
+impl std::error::Error for Lagged {}
+
+#[derive(Debug)]
+struct ChannelState<T, K> {
+    buf: VecDeque<ChangeEvent<T, K>>,
+    lagged: Option<u64>,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Channel<T, K> {
+    state: Mutex<ChannelState<T, K>>,
+    ready: Condvar,
+}
+
+/// Publishes the changes committed to a book to its subscribers, keeping the
+/// most recent ones so a subscriber can resume from a sequence number.
+#[derive(Debug)]
+pub struct ChangeFeed<T: Persistable, K: BookKey = Key> {
+    capacity: usize,
+    next_seq: u64,
+    history: VecDeque<ChangeEvent<T, K>>,
+    subscribers: Vec<Weak<Channel<T, K>>>,
+}
+
+impl<T: Persistable, K: BookKey> ChangeFeed<T, K> {
+    pub fn new(capacity: usize) -> Self {
+        ChangeFeed {
+            capacity: capacity.max(1),
+            next_seq: 0,
+            history: VecDeque::new(),
+            subscribers: vec![],
+        }
+    }
+
+    /// Record a committed change and hand it to every subscriber with room for it.
+    pub fn publish(&mut self, key: K, old: Option<T>, new: Option<T>) {
+        let kind = match (&old, &new) {
+            (_, None) => ChangeKind::Delete,
+            (None, Some(_)) => ChangeKind::Insert,
+            (Some(_), Some(_)) => ChangeKind::Replace,
+        };
+
+        let event = ChangeEvent {
+            seq: self.next_seq,
+            key,
+            kind,
+            old,
+            new,
+        };
+        self.next_seq += 1;
+
+        if self.history.len() == self.capacity {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(event);
+
+        let capacity = self.capacity;
+
+        self.subscribers.retain(|weak| {
+            let channel = if let Some(channel) = weak.upgrade() {
+                channel
+            } else {
+                return false;
+            };
+
+            let mut state = channel.state.lock();
+
+            if state.lagged.is_some() {
+                return false;
+            }
+
+            if state.buf.len() == capacity {
+                // note: the subscriber gets what it has buffered, then learns where to resume
+                state.lagged = Some(event.seq);
+            } else {
+                state.buf.push_back(event);
+            }
+
+            channel.ready.notify_all();
+
+            state.lagged.is_none()
+        });
+    }
+
+    /// Subscribe to changes from `from` on, or only to new ones. Fails if `from`
+    /// has already dropped out of the retained history.
+    pub fn subscribe(&mut self, from: Option<u64>) -> anyhow::Result<Subscription<T, K>> {
+        let from = from.unwrap_or(self.next_seq);
+        let oldest = self
+            .history
+            .front()
+            .map_or(self.next_seq, |event| event.seq);
+
+        if from < oldest {
+            anyhow::bail!(
+                "change {} is no longer retained, the oldest is {}",
+                from,
+                oldest
+            );
+        }
+
+        if from > self.next_seq {
+            anyhow::bail!(
+                "change {} has not happened yet, the next is {}",
+                from,
+                self.next_seq
+            );
+        }
+
+        let channel = Arc::new(Channel {
+            state: Mutex::new(ChannelState {
+                buf: self
+                    .history
+                    .iter()
+                    .filter(|event| event.seq >= from)
+                    .copied()
+                    .collect(),
+                lagged: None,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        });
+
+        self.subscribers.push(Arc::downgrade(&channel));
+
+        Ok(Subscription { channel })
+    }
+}
+
+impl<T: Persistable, K: BookKey> Drop for ChangeFeed<T, K> {
+    fn drop(&mut self) {
+        for channel in self.subscribers.iter().filter_map(Weak::upgrade) {
+            channel.state.lock().closed = true;
+            channel.ready.notify_all();
+        }
+    }
+}
+
+/// Changes committed to a book, in commit order. Iterating blocks until the
+/// next change arrives and ends once the book is closed, or right after a
+/// `Lagged` if the subscriber fell behind.
+#[derive(Debug)]
+pub struct Subscription<T, K = Key> {
+    channel: Arc<Channel<T, K>>,
+}
+
+impl<T: Copy, K: Copy> Subscription<T, K> {
+    /// The next change if one is waiting, without blocking.
+    pub fn try_recv(&self) -> Option<Result<ChangeEvent<T, K>, Lagged>> {
+        Self::take(&mut self.channel.state.lock())
+    }
+
+    /// Wait up to `timeout` for the next change.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Result<ChangeEvent<T, K>, Lagged>> {
+        let mut state = self.channel.state.lock();
+
+        if state.buf.is_empty() && state.lagged.is_none() && !state.closed {
+            self.channel.ready.wait_for(&mut state, timeout);
+        }
+
+        Self::take(&mut state)
+    }
+
+    fn take(state: &mut ChannelState<T, K>) -> Option<Result<ChangeEvent<T, K>, Lagged>> {
+        if let Some(event) = state.buf.pop_front() {
+            return Some(Ok(event));
+        }
+
+        // note: reported once, after which the subscription is closed
+        let resume_from = state.lagged.take()?;
+        state.closed = true;
+
+        Some(Err(Lagged { resume_from }))
+    }
+}
+
+impl<T: Copy, K: Copy> Iterator for Subscription<T, K> {
+    type Item = Result<ChangeEvent<T, K>, Lagged>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut state = self.channel.state.lock();
+
+        while state.buf.is_empty() && state.lagged.is_none() && !state.closed {
+            self.channel.ready.wait(&mut state);
+        }
+
+        Self::take(&mut state)
+    }
+}
@@ -0,0 +1,176 @@
+use std::{collections::VecDeque, ops::Bound};
+
+use crate::{book::Book, book_key::BookKey, persistable::Persistable, Idx, Key};
+
+/// Entries a `RangeCursor` reads per acquisition of the book lock.
+const RANGE_BATCH: usize = 64;
+
+/// A resumable position within a book: the next slot a `Cursor` will inspect.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CursorPos {
+    pub page: Idx,
+    pub slot: Idx,
+}
+
+impl CursorPos {
+    #[inline]
+    pub fn new(page: Idx, slot: Idx) -> Self {
+        Self { page, slot }
+    }
+}
+
+/// Walks every live entry of a book in `Idx` order.
+///
+/// The book and page locks are only held while a single entry is being read,
+/// so writers may interleave with a long-running scan. Entries inserted behind
+/// the cursor are not observed; entries deleted ahead of it are skipped. Pages
+/// are mapped as the cursor reaches them, and a page that fails to load ends
+/// the scan with its error.
+#[derive(Debug)]
+pub struct Cursor<T: Persistable, K: BookKey = Key> {
+    book: Book<T, K>,
+    pos: CursorPos,
+    failed: bool,
+}
+
+impl<T: Persistable, K: BookKey> Cursor<T, K> {
+    pub fn new(book: Book<T, K>, pos: CursorPos) -> Self {
+        Self {
+            book,
+            pos,
+            failed: false,
+        }
+    }
+
+    /// The position to pass to `Book::cursor_at` to resume after the last yielded entry.
+    #[inline]
+    pub fn pos(&self) -> CursorPos {
+        self.pos
+    }
+}
+
+impl<T: Persistable, K: BookKey> Iterator for Cursor<T, K> {
+    type Item = anyhow::Result<(K, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        loop {
+            let page = {
+                let book_guard = self.book.shared();
+
+                let (page_idx, page) = match book_guard.next_page(self.pos.page) {
+                    Ok(next) => next?,
+                    Err(e) => {
+                        // note: `pos` is left in place so the scan can be resumed
+                        self.failed = true;
+                        return Some(Err(e));
+                    }
+                };
+
+                // note: skip over pages reclaimed by compaction
+                if page_idx != self.pos.page {
+                    self.pos = CursorPos::new(page_idx, Idx::default());
+                }
+
+                page
+            };
+
+            let found = page.read().next_entry(self.pos.slot);
+
+            match found {
+                Some((slot, key, val)) => {
+                    self.pos.slot = Idx::new(slot.val + 1);
+                    return Some(Ok((key, val)));
+                }
+                None => {
+                    self.pos = CursorPos::new(Idx::new(self.pos.page.val + 1), Idx::default());
+                }
+            }
+        }
+    }
+}
+
+/// Walks the entries of a book whose keys fall within a range, in `K` order
+/// or in reverse.
+///
+/// Entries are read in small batches, each seeking the key index afresh past
+/// the last key yielded, so the book is only locked while a batch is read. As
+/// with `Cursor`, writers may interleave: entries are observed as they are
+/// when their batch is read. The range left to walk is available as `bounds`,
+/// so a page of results can be resumed later.
+#[derive(Debug)]
+pub struct RangeCursor<T: Persistable, K: BookKey = Key> {
+    book: Book<T, K>,
+    start: Bound<K>,
+    end: Bound<K>,
+    rev: bool,
+    buf: VecDeque<(K, T)>,
+    done: bool,
+}
+
+impl<T: Persistable, K: BookKey> RangeCursor<T, K> {
+    pub fn new(book: Book<T, K>, start: Bound<K>, end: Bound<K>, rev: bool) -> Self {
+        Self {
+            book,
+            start,
+            end,
+            rev,
+            buf: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// The part of the range not yielded yet.
+    pub fn bounds(&self) -> (Bound<K>, Bound<K>) {
+        match (self.buf.front(), self.rev) {
+            (Some((key, _)), false) => (Bound::Included(*key), self.end),
+            (Some((key, _)), true) => (self.start, Bound::Included(*key)),
+            (None, _) => (self.start, self.end),
+        }
+    }
+
+    fn fill(&mut self) -> anyhow::Result<()> {
+        let book_guard = self.book.shared();
+        let range = book_guard.range((self.start, self.end));
+
+        let batch = if self.rev {
+            range
+                .rev()
+                .take(RANGE_BATCH)
+                .collect::<anyhow::Result<Vec<_>>>()?
+        } else {
+            range
+                .take(RANGE_BATCH)
+                .collect::<anyhow::Result<Vec<_>>>()?
+        };
+
+        match batch.last() {
+            Some((key, _)) if self.rev => self.end = Bound::Excluded(*key),
+            Some((key, _)) => self.start = Bound::Excluded(*key),
+            None => {}
+        }
+
+        self.done = batch.len() < RANGE_BATCH;
+        self.buf.extend(batch);
+
+        Ok(())
+    }
+}
+
+impl<T: Persistable, K: BookKey> Iterator for RangeCursor<T, K> {
+    type Item = anyhow::Result<(K, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() && !self.done {
+            if let Err(e) = self.fill() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        self.buf.pop_front().map(Ok)
+    }
+}
@@ -0,0 +1,485 @@
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backup::{copy_dir, copy_dir_atomic, publish_dir, sibling_path, validate_book},
+    book::{Book, WeakBook},
+    book_key::BookKey,
+    options::BookOptions,
+    page_header::entry_fingerprint,
+    persistable::Persistable,
+    BookId, Key, DATA_DIR,
+};
+
+pub const CATALOG_FORMAT_VERSION: u32 = 1;
+
+/// What the catalog records about each named book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub id: BookId,
+    pub value_type: String,
+    #[serde(default = "default_key_type")]
+    pub key_type: String,
+    pub fingerprint: u64,
+    pub options: BookOptions,
+}
+
+fn default_key_type() -> String {
+    std::any::type_name::<Key>().to_string()
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Catalog {
+    version: u32,
+    books: BTreeMap<String, CatalogEntry>,
+}
+
+/// Type-erased weak handle to a book the database has handed out.
+trait OpenBook: Send + Sync {
+    fn is_live(&self) -> bool;
+    fn as_any(&self) -> &dyn Any;
+    /// A handle that keeps the book open, if it still is.
+    fn pin(&self) -> Option<Box<dyn PinnedBook>>;
+}
+
+impl<T: Persistable, K: BookKey> OpenBook for WeakBook<T, K> {
+    fn is_live(&self) -> bool {
+        self.upgrade().is_some()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn pin(&self) -> Option<Box<dyn PinnedBook>> {
+        self.upgrade()
+            .map(|book| Box::new(book) as Box<dyn PinnedBook>)
+    }
+}
+
+/// Type-erased strong handle to an open book.
+trait PinnedBook: Send + Sync {
+    /// See `Book::snapshot`.
+    fn snapshot(&self, dest: &Path) -> anyhow::Result<()>;
+}
+
+impl<T: Persistable, K: BookKey> PinnedBook for Book<T, K> {
+    fn snapshot(&self, dest: &Path) -> anyhow::Result<()> {
+        Book::snapshot(self, dest)
+    }
+}
+
+#[derive(Default)]
+struct DatabaseState {
+    catalog: Catalog,
+    open: HashMap<BookId, Box<dyn OpenBook>>,
+}
+
+/// A set of books addressed by name, backed by a catalog file in its root directory.
+pub struct Database {
+    root: PathBuf,
+    temp: bool,
+    state: Mutex<DatabaseState>,
+}
+
+impl Database {
+    /// Open the database in the default `DATA_DIR`.
+    pub fn new() -> anyhow::Result<Self> {
+        Self::open(&*DATA_DIR)
+    }
+
+    /// Open (or create) the database rooted at `root`.
+    pub fn open(root: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+
+        fs::create_dir_all(&root)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to ensure {:?}", root)))?;
+
+        let catalog = load_catalog(&root)?;
+
+        Ok(Database {
+            root,
+            temp: false,
+            state: Mutex::new(DatabaseState {
+                catalog,
+                open: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Create an empty database in a fresh temporary directory, removed again on drop.
+    pub fn temp() -> anyhow::Result<Self> {
+        let root =
+            std::env::temp_dir().join(format!("experimental-db-{:016x}", rand::random::<u64>()));
+
+        if root.exists() {
+            anyhow::bail!("temporary directory {:?} already exists", root);
+        }
+
+        let mut db = Self::open(root)?;
+        db.temp = true;
+
+        Ok(db)
+    }
+
+    #[inline]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn create_book<T: Persistable>(
+        &self,
+        name: &str,
+        options: BookOptions,
+    ) -> anyhow::Result<Book<T>> {
+        self.create_book_with_key::<T, Key>(name, options)
+    }
+
+    /// Create a book keyed by `K` rather than `Key`.
+    pub fn create_book_with_key<T: Persistable, K: BookKey>(
+        &self,
+        name: &str,
+        options: BookOptions,
+    ) -> anyhow::Result<Book<T, K>> {
+        let mut state = self.state.lock();
+
+        if state.catalog.books.contains_key(name) {
+            anyhow::bail!("book {:?} already exists", name);
+        }
+
+        let id = loop {
+            let id = BookId::rand();
+            let taken = state.catalog.books.values().any(|entry| entry.id == id);
+
+            if !taken && !self.root.join(format!("books/{}", id.val)).exists() {
+                break id;
+            }
+        };
+
+        let book = Book::<T, K>::with_options_in(&self.root, id, options)?;
+        let entry = CatalogEntry {
+            name: name.to_string(),
+            id,
+            value_type: std::any::type_name::<T>().to_string(),
+            key_type: std::any::type_name::<K>().to_string(),
+            fingerprint: entry_fingerprint::<T, K>(),
+            options: book.read().options().clone(),
+        };
+
+        state.catalog.books.insert(name.to_string(), entry);
+        store_catalog(&self.root, &state.catalog)?;
+        state.open.insert(id, Box::new(book.downgrade()));
+
+        Ok(book)
+    }
+
+    /// Open a named book, sharing the handle with any other live opener.
+    pub fn open_book<T: Persistable>(&self, name: &str) -> anyhow::Result<Book<T>> {
+        self.open_book_with_key::<T, Key>(name)
+    }
+
+    /// Open a named book keyed by `K`, as created by `create_book_with_key`.
+    pub fn open_book_with_key<T: Persistable, K: BookKey>(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<Book<T, K>> {
+        let mut state = self.state.lock();
+
+        let entry = if let Some(entry) = state.catalog.books.get(name) {
+            entry.clone()
+        } else {
+            anyhow::bail!("book {:?} not found", name)
+        };
+
+        if entry.fingerprint != entry_fingerprint::<T, K>() {
+            anyhow::bail!(
+                "book {:?} holds `{}` keyed by `{}`, not `{}` keyed by `{}`",
+                name,
+                entry.value_type,
+                entry.key_type,
+                std::any::type_name::<T>(),
+                std::any::type_name::<K>()
+            );
+        }
+
+        let live = state
+            .open
+            .get(&entry.id)
+            .and_then(|weak| weak.as_any().downcast_ref::<WeakBook<T, K>>())
+            .and_then(|weak| weak.upgrade());
+
+        if let Some(book) = live {
+            return Ok(book);
+        }
+
+        let book = Book::<T, K>::with_options_in(&self.root, entry.id, entry.options)?;
+        state.open.insert(entry.id, Box::new(book.downgrade()));
+
+        Ok(book)
+    }
+
+    pub fn list_books(&self) -> Vec<CatalogEntry> {
+        self.state.lock().catalog.books.values().cloned().collect()
+    }
+
+    pub fn rename_book(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        let mut state = self.state.lock();
+
+        if state.catalog.books.contains_key(to) {
+            anyhow::bail!("book {:?} already exists", to);
+        }
+
+        let mut entry = if let Some(entry) = state.catalog.books.remove(from) {
+            entry
+        } else {
+            anyhow::bail!("book {:?} not found", from)
+        };
+
+        entry.name = to.to_string();
+        state.catalog.books.insert(to.to_string(), entry);
+
+        if let Err(e) = store_catalog(&self.root, &state.catalog) {
+            let mut entry = state
+                .catalog
+                .books
+                .remove(to)
+                .expect("entry was just inserted");
+            entry.name = from.to_string();
+            state.catalog.books.insert(from.to_string(), entry);
+
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Remove a book from the catalog and delete its files. Fails while it is open.
+    pub fn drop_book(&self, name: &str) -> anyhow::Result<()> {
+        let mut state = self.state.lock();
+
+        let entry = if let Some(entry) = state.catalog.books.get(name) {
+            entry.clone()
+        } else {
+            anyhow::bail!("book {:?} not found", name)
+        };
+
+        if state.open.get(&entry.id).is_some_and(|weak| weak.is_live()) {
+            anyhow::bail!("book {:?} is still open", name);
+        }
+
+        state.catalog.books.remove(name);
+        store_catalog(&self.root, &state.catalog)?;
+        state.open.remove(&entry.id);
+
+        let book_dir = self.root.join(format!("books/{}", entry.id.val));
+
+        fs::remove_dir_all(&book_dir)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to remove {:?}", book_dir)))?;
+
+        Ok(())
+    }
+
+    /// Copy the catalog and every book to `dest`, which must not exist, as a
+    /// database that `Database::open` or `restore` accepts. Open books are
+    /// snapshotted. The rest are copied as they are on disk, while books cannot
+    /// be created, opened or dropped, and validated like `restore` does, so a
+    /// book last closed by a crash has to be opened once before it is backed up.
+    pub fn backup(&self, dest: impl AsRef<Path>) -> anyhow::Result<()> {
+        let dest = dest.as_ref();
+
+        if dest.exists() {
+            anyhow::bail!("{:?} already exists", dest);
+        }
+
+        let staging = sibling_path(dest, "partial");
+
+        if staging.exists() {
+            fs::remove_dir_all(&staging).map_err(|e| {
+                anyhow::anyhow!(e).context(format!("failed to remove {:?}", staging))
+            })?;
+        }
+
+        let copied = (|| {
+            fs::create_dir_all(staging.join("books")).map_err(|e| {
+                anyhow::anyhow!(e).context(format!("failed to ensure {:?}", staging))
+            })?;
+
+            let (catalog, pinned) = {
+                let state = self.state.lock();
+                let mut pinned = vec![];
+
+                for entry in state.catalog.books.values() {
+                    let book_dest = staging.join(format!("books/{}", entry.id.val));
+
+                    if let Some(book) = state.open.get(&entry.id).and_then(|weak| weak.pin()) {
+                        pinned.push((book, book_dest));
+                        continue;
+                    }
+
+                    let book_dir = self.root.join(format!("books/{}", entry.id.val));
+                    copy_dir_atomic(&book_dir, &book_dest)?;
+
+                    validate_book(&book_dest, entry.fingerprint).map_err(|e| {
+                        e.context(format!(
+                            "book {:?} cannot be copied as it is on disk, open it once to recover it",
+                            entry.name
+                        ))
+                    })?;
+                }
+
+                (state.catalog.clone(), pinned)
+            };
+
+            // note: snapshots take each book's lock, so the state lock must not be
+            // held, or a writer holding a book while it opens another deadlocks
+            for (book, book_dest) in pinned {
+                book.snapshot(&book_dest)?;
+            }
+
+            store_catalog(&staging, &catalog)
+        })();
+
+        if let Err(e) = copied {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e.context(format!("failed to back up to {:?}", dest)));
+        }
+
+        publish_dir(&staging, dest)
+    }
+
+    /// Replace the whole database with the backup in `src`. The backup is
+    /// copied next to the root and every book in it validated before it is
+    /// swapped in, so a damaged backup leaves the database as it was. Fails
+    /// while any book is open.
+    pub fn restore(&self, src: impl AsRef<Path>) -> anyhow::Result<()> {
+        let mut state = self.state.lock();
+        let src = src.as_ref();
+
+        for entry in state.catalog.books.values() {
+            if state.open.get(&entry.id).is_some_and(|weak| weak.is_live()) {
+                anyhow::bail!("book {:?} is still open", entry.name);
+            }
+        }
+
+        if !src.join("catalog").exists() {
+            anyhow::bail!("{:?} is not a database backup", src);
+        }
+
+        let staging = sibling_path(&self.root, "restoring");
+
+        if staging.exists() {
+            fs::remove_dir_all(&staging).map_err(|e| {
+                anyhow::anyhow!(e).context(format!("failed to remove {:?}", staging))
+            })?;
+        }
+
+        let validated = copy_dir(src, &staging).and_then(|_| {
+            let catalog = load_catalog(&staging)?;
+
+            for entry in catalog.books.values() {
+                let book_dir = staging.join(format!("books/{}", entry.id.val));
+
+                validate_book(&book_dir, entry.fingerprint).map_err(|e| {
+                    e.context(format!("backup of book {:?} is damaged", entry.name))
+                })?;
+            }
+
+            Ok(catalog)
+        });
+
+        let catalog = match validated {
+            Ok(catalog) => catalog,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(e.context(format!("failed to restore from {:?}", src)));
+            }
+        };
+
+        let retired = sibling_path(&self.root, "replaced");
+
+        if retired.exists() {
+            fs::remove_dir_all(&retired).map_err(|e| {
+                anyhow::anyhow!(e).context(format!("failed to remove {:?}", retired))
+            })?;
+        }
+
+        fs::rename(&self.root, &retired).map_err(|e| {
+            anyhow::anyhow!(e).context(format!("failed to move {:?} aside", self.root))
+        })?;
+
+        if let Err(e) = publish_dir(&staging, &self.root) {
+            fs::rename(&retired, &self.root)?;
+            return Err(e);
+        }
+
+        state.catalog = catalog;
+        state.open.clear();
+
+        fs::remove_dir_all(&retired)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to remove {:?}", retired)))?;
+
+        Ok(())
+    }
+}
+
+fn load_catalog(root: &Path) -> anyhow::Result<Catalog> {
+    let path = root.join("catalog");
+
+    let content = match fs::read(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Catalog {
+                version: CATALOG_FORMAT_VERSION,
+                books: BTreeMap::new(),
+            })
+        }
+        Err(e) => return Err(anyhow::anyhow!(e).context(format!("failed to read {:?}", path))),
+    };
+
+    let catalog: Catalog = serde_json::from_slice(&content)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to parse {:?}", path)))?;
+
+    if catalog.version != CATALOG_FORMAT_VERSION {
+        anyhow::bail!(
+            "catalog {:?} has version {}, expected {}",
+            path,
+            catalog.version,
+            CATALOG_FORMAT_VERSION
+        );
+    }
+
+    Ok(catalog)
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        // note: books still open from a temporary database lose their files here
+        if self.temp {
+            if let Err(e) = fs::remove_dir_all(&self.root) {
+                eprintln!("failed to remove {:?}: {}", self.root, e);
+            }
+        }
+    }
+}
+
+/// Write the catalog via a temporary file so a crash never leaves it half written.
+fn store_catalog(root: &Path, catalog: &Catalog) -> anyhow::Result<()> {
+    let path = root.join("catalog");
+    let tmp_path = root.join("catalog.tmp");
+
+    fs::write(&tmp_path, serde_json::to_vec_pretty(catalog)?)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", tmp_path)))?;
+    fs::File::open(&tmp_path)?.sync_all()?;
+    fs::rename(&tmp_path, &path)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", path)))?;
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::Idx;
+
+pub const FREE_SPACE_MAGIC: [u8; 8] = *b"XDBFREE\0";
+pub const FREE_SPACE_FORMAT_VERSION: u32 = 1;
+
+/// `[magic][version: u32][count: u32]` ahead of `[page: u32][len: u32]` pairs.
+const HEADER_BYTES: usize = 16;
+const ENTRY_BYTES: usize = 8;
+
+/// How many entries each live page holds, so pages with room can be found
+/// without mapping them. Persisted as `books/<id>/free` at every checkpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreeSpaceMap {
+    cap: usize,
+    lens: BTreeMap<Idx, usize>,
+    partial: BTreeSet<Idx>,
+    changed: bool,
+}
+
+impl FreeSpaceMap {
+    pub fn new(cap: usize) -> Self {
+        FreeSpaceMap {
+            cap,
+            lens: BTreeMap::new(),
+            partial: BTreeSet::new(),
+            changed: true,
+        }
+    }
+
+    #[inline]
+    pub fn path(book_dir: &Path) -> PathBuf {
+        book_dir.join("free")
+    }
+
+    /// Load the map of the book in `book_dir`, if it has one that covers exactly `live` pages.
+    pub fn load(book_dir: &Path, cap: usize, live: &BTreeSet<Idx>) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(book_dir);
+
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(anyhow::anyhow!(e).context(format!("failed to read {:?}", path))),
+        };
+
+        if content.len() < HEADER_BYTES
+            || content[..8] != FREE_SPACE_MAGIC
+            || u32::from_le_bytes(content[8..12].try_into()?) != FREE_SPACE_FORMAT_VERSION
+        {
+            return Ok(None);
+        }
+
+        let count = u32::from_le_bytes(content[12..16].try_into()?) as usize;
+
+        if content.len() != HEADER_BYTES + count * ENTRY_BYTES {
+            return Ok(None);
+        }
+
+        let mut map = Self::new(cap);
+
+        for chunk in content[HEADER_BYTES..].chunks_exact(ENTRY_BYTES) {
+            let page_idx = Idx::new(u32::from_le_bytes(chunk[..4].try_into()?));
+            let len = u32::from_le_bytes(chunk[4..].try_into()?) as usize;
+
+            if len > cap {
+                return Ok(None);
+            }
+
+            map.set(page_idx, len);
+        }
+
+        if map.lens.keys().ne(live.iter()) {
+            return Ok(None);
+        }
+
+        map.changed = false;
+
+        Ok(Some(map))
+    }
+
+    /// Write the map via a temporary file, if it changed since it was last stored.
+    pub fn store(&mut self, book_dir: &Path) -> anyhow::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let path = Self::path(book_dir);
+        let tmp_path = book_dir.join("free.tmp");
+
+        let mut buf = Vec::with_capacity(HEADER_BYTES + self.lens.len() * ENTRY_BYTES);
+        buf.extend_from_slice(&FREE_SPACE_MAGIC);
+        buf.extend_from_slice(&FREE_SPACE_FORMAT_VERSION.to_le_bytes());
+        buf.extend_from_slice(&(self.lens.len() as u32).to_le_bytes());
+
+        for (page_idx, len) in &self.lens {
+            buf.extend_from_slice(&page_idx.val.to_le_bytes());
+            buf.extend_from_slice(&(*len as u32).to_le_bytes());
+        }
+
+        fs::write(&tmp_path, &buf)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", tmp_path)))?;
+        fs::File::open(&tmp_path)?.sync_all()?;
+        fs::rename(&tmp_path, &path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", path)))?;
+
+        self.changed = false;
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    /// The number of live pages.
+    #[inline]
+    pub fn page_count(&self) -> usize {
+        self.lens.len()
+    }
+
+    #[inline]
+    pub fn contains(&self, page_idx: Idx) -> bool {
+        self.lens.contains_key(&page_idx)
+    }
+
+    #[inline]
+    pub fn len_of(&self, page_idx: Idx) -> Option<usize> {
+        self.lens.get(&page_idx).copied()
+    }
+
+    /// Live pages in `Idx` order.
+    pub fn pages(&self) -> impl Iterator<Item = Idx> + '_ {
+        self.lens.keys().copied()
+    }
+
+    /// Live pages with at least one vacant slot, in `Idx` order.
+    pub fn partial(&self) -> impl Iterator<Item = Idx> + '_ {
+        self.partial.iter().copied()
+    }
+
+    /// The lowest index not held by a live page.
+    pub fn first_vacant_idx(&self) -> Idx {
+        Idx::new(
+            self.lens
+                .keys()
+                .enumerate()
+                .find(|(i, page_idx)| *i as u32 != page_idx.val)
+                .map_or(self.lens.len(), |(i, _)| i) as u32,
+        )
+    }
+
+    pub fn set(&mut self, page_idx: Idx, len: usize) {
+        self.lens.insert(page_idx, len);
+
+        if len < self.cap {
+            self.partial.insert(page_idx);
+        } else {
+            self.partial.remove(&page_idx);
+        }
+
+        self.changed = true;
+    }
+
+    pub fn adjust(&mut self, page_idx: Idx, added: bool) {
+        let len = self.len_of(page_idx).unwrap_or_default();
+        self.set(page_idx, if added { len + 1 } else { len - 1 });
+    }
+
+    pub fn remove(&mut self, page_idx: Idx) {
+        self.lens.remove(&page_idx);
+        self.partial.remove(&page_idx);
+        self.changed = true;
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    mem::{size_of, MaybeUninit},
+    path::{Path, PathBuf},
+};
+
+use parking_lot::Mutex;
+
+use crate::persistable::Persistable;
+
+const BLOCK_FREE: u32 = 0;
+const BLOCK_LIVE: u32 = 1;
+
+/// `[cap: u32][state: u32]` ahead of every block's payload.
+const BLOCK_HEADER_BYTES: u64 = 8;
+const BLOCK_ALIGN: u32 = 8;
+
+/// Untyped handle to a variable-length payload in a book's heap.
+///
+/// Handles are plain data, so they can be stored inside fixed-size page entries.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HeapRef {
+    pub offset: u64,
+    pub len: u32,
+    pub cap: u32,
+}
+
+/// Handle to a UTF-8 string stored in a book's heap.
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HeapStr {
+    pub raw: HeapRef,
+}
+
+impl From<HeapStr> for HeapRef {
+    fn from(handle: HeapStr) -> Self {
+        handle.raw
+    }
+}
+
+/// Handle to a sequence of `V` stored in a book's heap.
+#[repr(transparent)]
+pub struct HeapVec<V> {
+    pub raw: HeapRef,
+    _marker: PhantomData<V>,
+}
+
+impl<V> HeapVec<V> {
+    #[inline]
+    pub fn new(raw: HeapRef) -> Self {
+        Self {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.raw.len as usize / size_of::<V>().max(1)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.raw.len == 0
+    }
+}
+
+impl<V> Clone for HeapVec<V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<V> Copy for HeapVec<V> {}
+
+impl<V> Default for HeapVec<V> {
+    fn default() -> Self {
+        Self::new(HeapRef::default())
+    }
+}
+
+impl<V> PartialEq for HeapVec<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl<V> Eq for HeapVec<V> {}
+
+impl<V> std::fmt::Debug for HeapVec<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HeapVec({:?})", self.raw)
+    }
+}
+
+impl<V> From<HeapVec<V>> for HeapRef {
+    fn from(handle: HeapVec<V>) -> Self {
+        handle.raw
+    }
+}
+
+/// Overflow storage for payloads that do not fit in a fixed-size page entry.
+///
+/// The heap file is a sequence of blocks, each `[cap][state][payload]`. Freed
+/// blocks are reused first-fit by later allocations of the same or smaller size.
+#[derive(Debug)]
+pub struct Heap {
+    path: PathBuf,
+    file: Mutex<File>,
+    len: u64,
+    free: BTreeMap<u32, BTreeSet<u64>>,
+}
+
+impl Heap {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to open {:?}", path)))?;
+
+        let len = file.metadata()?.len();
+        let mut free = BTreeMap::<u32, BTreeSet<u64>>::new();
+        let mut offset = 0;
+
+        while offset + BLOCK_HEADER_BYTES <= len {
+            let (cap, state) = read_header(&mut file, offset)?;
+
+            if state == BLOCK_FREE {
+                free.entry(cap).or_default().insert(offset);
+            }
+
+            offset += BLOCK_HEADER_BYTES + cap as u64;
+        }
+
+        if offset != len {
+            anyhow::bail!("heap {:?} ends in a truncated block", path);
+        }
+
+        Ok(Heap {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            len,
+            free,
+        })
+    }
+
+    /// Store `bytes` in a new block, syncing the heap before returning when `sync` is set.
+    pub fn alloc(&mut self, bytes: &[u8], sync: bool) -> anyhow::Result<HeapRef> {
+        let len = u32::try_from(bytes.len())
+            .map_err(|_| anyhow::anyhow!("heap payloads are limited to {} bytes", u32::MAX))?;
+
+        let reused = self
+            .free
+            .range(len..)
+            .next()
+            .and_then(|(cap, offsets)| offsets.first().map(|offset| (*cap, *offset)));
+
+        let (cap, offset) =
+            reused.unwrap_or_else(|| (len.div_ceil(BLOCK_ALIGN).max(1) * BLOCK_ALIGN, self.len));
+
+        let mut block = Vec::with_capacity(BLOCK_HEADER_BYTES as usize + cap as usize);
+        block.extend_from_slice(&cap.to_le_bytes());
+        block.extend_from_slice(&BLOCK_LIVE.to_le_bytes());
+        block.extend_from_slice(bytes);
+        block.resize(BLOCK_HEADER_BYTES as usize + cap as usize, 0);
+
+        let mut file = self.file.lock();
+
+        let written = file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| file.write_all(&block))
+            .and_then(|_| if sync { file.sync_data() } else { Ok(()) });
+
+        if let Err(e) = written {
+            // note: a block appended in part would make the heap fail to open
+            if reused.is_none() {
+                let _ = file.set_len(self.len);
+            }
+
+            return Err(anyhow::anyhow!(e).context(format!("failed to write {:?}", self.path)));
+        }
+
+        // note: the block is only taken once it is written, so a failure loses nothing
+        match reused {
+            Some((cap, offset)) => {
+                if let Some(offsets) = self.free.get_mut(&cap) {
+                    offsets.remove(&offset);
+
+                    if offsets.is_empty() {
+                        self.free.remove(&cap);
+                    }
+                }
+            }
+            None => self.len += BLOCK_HEADER_BYTES + cap as u64,
+        }
+
+        Ok(HeapRef { offset, len, cap })
+    }
+
+    pub fn read(&self, handle: HeapRef) -> anyhow::Result<Vec<u8>> {
+        let mut file = self.file.lock();
+        self.validate(&mut file, handle)?;
+
+        let mut bytes = vec![0; handle.len as usize];
+        file.read_exact(&mut bytes)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to read {:?}", self.path)))?;
+
+        Ok(bytes)
+    }
+
+    /// Check that `handle` refers to a live block.
+    pub fn check(&self, handle: HeapRef) -> anyhow::Result<()> {
+        self.validate(&mut self.file.lock(), handle)
+    }
+
+    /// Mark the block behind `handle` as reusable.
+    pub fn free(&mut self, handle: HeapRef) -> anyhow::Result<()> {
+        let mut file = self.file.lock();
+        self.validate(&mut file, handle)?;
+
+        file.seek(SeekFrom::Start(handle.offset + 4))?;
+        file.write_all(&BLOCK_FREE.to_le_bytes())?;
+
+        self.free
+            .entry(handle.cap)
+            .or_default()
+            .insert(handle.offset);
+
+        Ok(())
+    }
+
+    /// Check `handle` against the heap's bounds and its block header, leaving
+    /// `file` positioned at the payload.
+    fn validate(&self, file: &mut File, handle: HeapRef) -> anyhow::Result<()> {
+        if handle.len > handle.cap
+            || handle.offset + BLOCK_HEADER_BYTES + handle.cap as u64 > self.len
+        {
+            anyhow::bail!("heap handle {:?} is out of bounds", handle);
+        }
+
+        let (cap, state) = read_header(file, handle.offset)?;
+
+        if state != BLOCK_LIVE || cap != handle.cap {
+            anyhow::bail!("heap handle {:?} does not refer to a live block", handle);
+        }
+
+        Ok(())
+    }
+
+    pub fn sync(&self) -> anyhow::Result<()> {
+        self.file
+            .lock()
+            .sync_data()
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to sync {:?}", self.path)))?;
+
+        Ok(())
+    }
+}
+
+fn read_header(file: &mut File, offset: u64) -> anyhow::Result<(u32, u32)> {
+    let mut header = [0; BLOCK_HEADER_BYTES as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+
+    let cap = u32::from_le_bytes(header[..4].try_into()?);
+    let state = u32::from_le_bytes(header[4..].try_into()?);
+
+    Ok((cap, state))
+}
+
+/// View a slice of plain values as raw bytes for storage in the heap.
+pub(crate) fn slice_bytes<V: Persistable>(vals: &[V]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(vals.as_ptr() as *const u8, std::mem::size_of_val(vals)) }
+}
+
+/// Rebuild plain values from bytes previously produced by `slice_bytes`.
+pub(crate) fn bytes_to_vec<V: Persistable>(bytes: &[u8]) -> anyhow::Result<Vec<V>> {
+    let size = size_of::<V>();
+
+    if size == 0 || !bytes.len().is_multiple_of(size) {
+        anyhow::bail!(
+            "heap payload of {} bytes is not a whole number of {}-byte values",
+            bytes.len(),
+            size
+        );
+    }
+
+    Ok(bytes
+        .chunks_exact(size)
+        .map(|chunk| unsafe {
+            let mut val = MaybeUninit::<V>::uninit();
+            std::ptr::copy_nonoverlapping(chunk.as_ptr(), val.as_mut_ptr() as *mut u8, size);
+            val.assume_init()
+        })
+        .collect())
+}
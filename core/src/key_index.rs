@@ -0,0 +1,547 @@
+use std::{
+    collections::{btree_map, BTreeMap},
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom, Write},
+    mem::size_of,
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+};
+
+use memmap2::Mmap;
+
+use crate::{book_key::BookKey, Idx, Key};
+
+pub const KEY_INDEX_MAGIC: [u8; 8] = *b"XDBKEYS\0";
+pub const KEY_INDEX_FORMAT_VERSION: u32 = 1;
+
+/// `[magic][version: u32][clean: u32][count: u64]` ahead of the sorted entries.
+const HEADER_BYTES: usize = 24;
+const CLEAN_OFFSET: u64 = 12;
+/// Page number marking a removal in the change log.
+const REMOVED: u32 = u32::MAX;
+/// Changes held in the log before they are merged into the sorted base.
+const MIN_MERGE_CHANGES: usize = 4096;
+
+/// Persistent map from every key in a book to the page holding it.
+///
+/// A sorted base file (`keys`) is memory mapped and binary searched, so lookups
+/// only fault in the pages they touch. Changes since the last merge live in a
+/// small in-memory map and are appended to a log (`keys.log`) on every sync;
+/// the log is folded into a fresh base once it grows past a fraction of it.
+///
+/// The base header carries a clean flag that is cleared before the first change
+/// after a sync and set again by the next one. An index that was not clean when
+/// opened may disagree with the pages and must be rebuilt from them.
+#[derive(Debug)]
+pub struct KeyIndex<K: BookKey = Key> {
+    dir: PathBuf,
+    base: Mmap,
+    base_len: usize,
+    log: File,
+    delta: BTreeMap<K, Option<Idx>>,
+    unlogged: Vec<(K, Option<Idx>)>,
+    len: usize,
+    clean: bool,
+}
+
+impl<K: BookKey> KeyIndex<K> {
+    /// `[key][page: u32]`, the key bytewise and the page little endian, sorted by key.
+    const ENTRY_BYTES: usize = size_of::<K>() + size_of::<u32>();
+
+    #[inline]
+    pub fn base_path(book_dir: &Path) -> PathBuf {
+        book_dir.join("keys")
+    }
+
+    #[inline]
+    pub fn log_path(book_dir: &Path) -> PathBuf {
+        book_dir.join("keys.log")
+    }
+
+    /// Open the index of the book in `book_dir`. Returns `None` when there is no
+    /// index yet, or when it was not cleanly synced and has to be rebuilt.
+    pub fn open(book_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let base_path = Self::base_path(book_dir);
+
+        let base_file = match File::open(&base_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(anyhow::anyhow!(e).context(format!("failed to open {:?}", base_path)))
+            }
+        };
+
+        let base = unsafe { Mmap::map(&base_file) }
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to map {:?}", base_path)))?;
+
+        let base_len = match read_header(&base) {
+            Some((true, base_len)) if base.len() == HEADER_BYTES + base_len * Self::ENTRY_BYTES => {
+                base_len
+            }
+            _ => return Ok(None),
+        };
+
+        let mut log = open_log::<K>(book_dir)?;
+        let mut content = vec![];
+        log.read_to_end(&mut content)?;
+
+        let mut index = KeyIndex {
+            dir: book_dir.to_path_buf(),
+            base,
+            base_len,
+            log,
+            delta: BTreeMap::new(),
+            unlogged: vec![],
+            len: base_len,
+            clean: true,
+        };
+
+        for chunk in content.chunks_exact(Self::ENTRY_BYTES) {
+            let (key, page) = decode_entry(chunk);
+
+            match page {
+                Some(page) => index.set(key, page),
+                None => index.unset(key),
+            };
+        }
+
+        index.unlogged.clear();
+
+        Ok(Some(index))
+    }
+
+    /// Write a fresh index holding exactly `entries`.
+    pub fn build(
+        book_dir: &Path,
+        entries: impl IntoIterator<Item = (K, Idx)>,
+    ) -> anyhow::Result<Self> {
+        let mut entries = entries.into_iter().collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(key, _)| *key);
+
+        write_base(book_dir, entries.into_iter(), true)?;
+        open_log::<K>(book_dir)?.set_len(0)?;
+
+        Self::open(book_dir)?
+            .ok_or_else(|| anyhow::anyhow!("freshly built key index in {:?} is unusable", book_dir))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: K) -> Option<Idx> {
+        match self.delta.get(&key) {
+            Some(page) => *page,
+            None => self.base_get(key),
+        }
+    }
+
+    #[inline]
+    pub fn contains_key(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Point `key` at `page`, returning the page it was in before.
+    pub fn insert(&mut self, key: K, page: Idx) -> anyhow::Result<Option<Idx>> {
+        self.mark_dirty()?;
+
+        Ok(self.set(key, page))
+    }
+
+    pub fn remove(&mut self, key: K) -> anyhow::Result<Option<Idx>> {
+        self.mark_dirty()?;
+
+        Ok(self.unset(key))
+    }
+
+    /// Every key and its page, in key order.
+    pub fn iter(&self) -> KeyIndexIter<'_, K> {
+        self.range(..)
+    }
+
+    /// Every key within `range` and its page, in key order. Seeking to either end
+    /// of the range is a binary search, however large the index.
+    pub fn range(&self, range: impl RangeBounds<K>) -> KeyIndexIter<'_, K> {
+        let (start, end) = (range.start_bound(), range.end_bound());
+
+        // note: `BTreeMap::range` panics on inverted bounds rather than yield nothing
+        let inverted = match (start, end) {
+            (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start > end,
+            _ => false,
+        };
+
+        if inverted {
+            return KeyIndexIter {
+                base: &self.base,
+                pos: 0,
+                end: 0,
+                delta: self.delta.range(..K::MIN),
+                front: None,
+                back: None,
+            };
+        }
+
+        let pos = match start {
+            Bound::Included(key) => self.base_rank(*key, false),
+            Bound::Excluded(key) => self.base_rank(*key, true),
+            Bound::Unbounded => 0,
+        };
+        let end_pos = match end {
+            Bound::Included(key) => self.base_rank(*key, true),
+            Bound::Excluded(key) => self.base_rank(*key, false),
+            Bound::Unbounded => self.base_len,
+        };
+
+        KeyIndexIter {
+            base: &self.base,
+            pos,
+            end: end_pos.max(pos),
+            delta: self.delta.range((start.cloned(), end.cloned())),
+            front: None,
+            back: None,
+        }
+    }
+
+    /// Clear the on-disk clean flag ahead of the first change since the last sync.
+    pub fn mark_dirty(&mut self) -> anyhow::Result<()> {
+        if !self.clean {
+            return Ok(());
+        }
+
+        write_clean_flag::<K>(&self.dir, false)?;
+        self.clean = false;
+
+        Ok(())
+    }
+
+    /// Persist every change made so far and mark the index clean. Only call once
+    /// the pages those changes describe have been flushed.
+    pub fn sync(&mut self) -> anyhow::Result<()> {
+        if self.clean {
+            return Ok(());
+        }
+
+        if self.delta.len() > MIN_MERGE_CHANGES.max(self.base_len / 8) {
+            self.merge()?;
+        } else if !self.unlogged.is_empty() {
+            let mut buf = Vec::with_capacity(self.unlogged.len() * Self::ENTRY_BYTES);
+
+            for (key, page) in self.unlogged.drain(..) {
+                encode_entry(key, page, &mut buf);
+            }
+
+            let log_path = Self::log_path(&self.dir);
+            self.log.seek(SeekFrom::End(0))?;
+            self.log.write_all(&buf).map_err(|e| {
+                anyhow::anyhow!(e).context(format!("failed to append to {:?}", log_path))
+            })?;
+            self.log.sync_data()?;
+        }
+
+        write_clean_flag::<K>(&self.dir, true)?;
+        self.clean = true;
+
+        Ok(())
+    }
+
+    /// Fold the change log into a new base file.
+    fn merge(&mut self) -> anyhow::Result<()> {
+        // note: the new base stays unclean until the old log is gone, or replaying
+        // that log over it would roll keys back
+        write_base(
+            &self.dir,
+            self.iter().collect::<Vec<_>>().into_iter(),
+            false,
+        )?;
+
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+
+        let base_path = Self::base_path(&self.dir);
+        let base_file = File::open(&base_path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to open {:?}", base_path)))?;
+
+        self.base = unsafe { Mmap::map(&base_file) }?;
+        self.base_len = self.len;
+        self.delta.clear();
+        self.unlogged.clear();
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: K, page: Idx) -> Option<Idx> {
+        let old = self.get(key);
+
+        if old.is_none() {
+            self.len += 1;
+        }
+
+        self.delta.insert(key, Some(page));
+        self.unlogged.push((key, Some(page)));
+
+        old
+    }
+
+    fn unset(&mut self, key: K) -> Option<Idx> {
+        let old = self.get(key);
+
+        if old.is_some() {
+            self.len -= 1;
+            self.delta.insert(key, None);
+            self.unlogged.push((key, None));
+        }
+
+        old
+    }
+
+    fn base_get(&self, key: K) -> Option<Idx> {
+        let (mut lo, mut hi) = (0, self.base_len);
+
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (found, page) = Self::base_entry(&self.base, mid);
+
+            match found.cmp(&key) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(page),
+            }
+        }
+
+        None
+    }
+
+    /// The number of base entries ordered before `key`, or up to and including
+    /// it if `inclusive`.
+    fn base_rank(&self, key: K, inclusive: bool) -> usize {
+        let (mut lo, mut hi) = (0, self.base_len);
+
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (found, _) = Self::base_entry(&self.base, mid);
+
+            if found < key || (inclusive && found == key) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+
+        lo
+    }
+
+    #[inline]
+    fn base_entry(base: &[u8], n: usize) -> (K, Idx) {
+        let start = HEADER_BYTES + n * Self::ENTRY_BYTES;
+        let (key, page) = decode_entry(&base[start..start + Self::ENTRY_BYTES]);
+
+        (key, page.expect("the base holds no removals"))
+    }
+}
+
+/// Merges the sorted base with the in-memory changes made since it was written.
+/// Walks the entries from either end.
+pub struct KeyIndexIter<'a, K: BookKey = Key> {
+    base: &'a [u8],
+    pos: usize,
+    end: usize,
+    delta: btree_map::Range<'a, K, Option<Idx>>,
+    front: Option<(K, Option<Idx>)>,
+    back: Option<(K, Option<Idx>)>,
+}
+
+impl<K: BookKey> KeyIndexIter<'_, K> {
+    fn peek_front(&mut self) -> Option<(K, Option<Idx>)> {
+        if self.front.is_none() {
+            // note: once the middle of the range is drained, the last change may
+            // already be waiting at the other end
+            self.front = self
+                .delta
+                .next()
+                .map(|(key, page)| (*key, *page))
+                .or_else(|| self.back.take());
+        }
+
+        self.front
+    }
+
+    fn peek_back(&mut self) -> Option<(K, Option<Idx>)> {
+        if self.back.is_none() {
+            self.back = self
+                .delta
+                .next_back()
+                .map(|(key, page)| (*key, *page))
+                .or_else(|| self.front.take());
+        }
+
+        self.back
+    }
+}
+
+impl<K: BookKey> Iterator for KeyIndexIter<'_, K> {
+    type Item = (K, Idx);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let base =
+                (self.pos < self.end).then(|| KeyIndex::<K>::base_entry(self.base, self.pos));
+            let delta = self.peek_front();
+
+            let (key, page) = match (base, delta) {
+                (None, None) => return None,
+                (Some(base), None) => {
+                    self.pos += 1;
+                    return Some(base);
+                }
+                (Some((base_key, base_page)), Some((key, _))) if base_key < key => {
+                    self.pos += 1;
+                    return Some((base_key, base_page));
+                }
+                (base, Some((key, page))) => {
+                    // note: a change to a key in the base shadows the base entry
+                    if base.is_some_and(|(base_key, _)| base_key == key) {
+                        self.pos += 1;
+                    }
+
+                    self.front = None;
+                    (key, page)
+                }
+            };
+
+            if let Some(page) = page {
+                return Some((key, page));
+            }
+        }
+    }
+}
+
+impl<K: BookKey> DoubleEndedIterator for KeyIndexIter<'_, K> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            let base =
+                (self.pos < self.end).then(|| KeyIndex::<K>::base_entry(self.base, self.end - 1));
+            let delta = self.peek_back();
+
+            let (key, page) = match (base, delta) {
+                (None, None) => return None,
+                (Some(base), None) => {
+                    self.end -= 1;
+                    return Some(base);
+                }
+                (Some((base_key, base_page)), Some((key, _))) if base_key > key => {
+                    self.end -= 1;
+                    return Some((base_key, base_page));
+                }
+                (base, Some((key, page))) => {
+                    if base.is_some_and(|(base_key, _)| base_key == key) {
+                        self.end -= 1;
+                    }
+
+                    self.back = None;
+                    (key, page)
+                }
+            };
+
+            if let Some(page) = page {
+                return Some((key, page));
+            }
+        }
+    }
+}
+
+fn read_header(base: &[u8]) -> Option<(bool, usize)> {
+    if base.len() < HEADER_BYTES || base[..8] != KEY_INDEX_MAGIC {
+        return None;
+    }
+
+    let version = u32::from_le_bytes(base[8..12].try_into().ok()?);
+    let clean = u32::from_le_bytes(base[12..16].try_into().ok()?);
+    let count = u64::from_le_bytes(base[16..24].try_into().ok()?);
+
+    if version != KEY_INDEX_FORMAT_VERSION {
+        return None;
+    }
+
+    Some((clean == 1, count as usize))
+}
+
+/// Write a base holding `entries`, which must be sorted, via a temporary file.
+fn write_base<K: BookKey>(
+    book_dir: &Path,
+    entries: impl ExactSizeIterator<Item = (K, Idx)>,
+    clean: bool,
+) -> anyhow::Result<()> {
+    let path = KeyIndex::<K>::base_path(book_dir);
+    let tmp_path = book_dir.join("keys.tmp");
+
+    let mut buf = Vec::with_capacity(HEADER_BYTES + entries.len() * KeyIndex::<K>::ENTRY_BYTES);
+    buf.extend_from_slice(&KEY_INDEX_MAGIC);
+    buf.extend_from_slice(&KEY_INDEX_FORMAT_VERSION.to_le_bytes());
+    buf.extend_from_slice(&(clean as u32).to_le_bytes());
+    buf.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+
+    for (key, page) in entries {
+        encode_entry(key, Some(page), &mut buf);
+    }
+
+    fs::write(&tmp_path, &buf)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", tmp_path)))?;
+    File::open(&tmp_path)?.sync_all()?;
+    fs::rename(&tmp_path, &path)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", path)))?;
+
+    Ok(())
+}
+
+fn write_clean_flag<K: BookKey>(book_dir: &Path, clean: bool) -> anyhow::Result<()> {
+    let path = KeyIndex::<K>::base_path(book_dir);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to open {:?}", path)))?;
+
+    file.seek(SeekFrom::Start(CLEAN_OFFSET))?;
+    file.write_all(&(clean as u32).to_le_bytes())
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", path)))?;
+    file.sync_data()?;
+
+    Ok(())
+}
+
+fn open_log<K: BookKey>(book_dir: &Path) -> anyhow::Result<File> {
+    let path = KeyIndex::<K>::log_path(book_dir);
+
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("failed to open {:?}", path)))
+}
+
+fn encode_entry<K: BookKey>(key: K, page: Option<Idx>, buf: &mut Vec<u8>) {
+    key.to_bytes(buf);
+    buf.extend_from_slice(&page.map_or(REMOVED, |page| page.val).to_le_bytes());
+}
+
+fn decode_entry<K: BookKey>(bytes: &[u8]) -> (K, Option<Idx>) {
+    let key = K::from_bytes(bytes);
+    let page = u32::from_le_bytes(
+        bytes[size_of::<K>()..size_of::<K>() + 4]
+            .try_into()
+            .expect("entries end in a page number"),
+    );
+
+    (key, (page != REMOVED).then_some(Idx::new(page)))
+}
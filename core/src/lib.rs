@@ -1,14 +1,37 @@
 use std::{path::PathBuf, sync::LazyLock};
 
 use petgraph::prelude::UnGraphMap;
+use serde::{Deserialize, Serialize};
 
+// note: lets `#[derive(Persistable)]` refer to this crate by name from within it
+extern crate self as experimental_db_core;
+
+pub mod backup;
 pub mod book;
+pub mod book_builder;
 pub mod book_inner;
+pub mod book_key;
+pub mod buffer_pool;
+pub mod changes;
+pub mod cursor;
+pub mod database;
+pub mod free_space;
+pub mod heap;
+pub mod key_index;
+pub mod manifest;
+pub mod ndjson;
+pub mod options;
 pub mod page;
 pub mod page_entry;
+pub mod page_header;
 pub mod page_inner;
 pub mod page_layout;
 pub mod page_meta;
+pub mod persistable;
+pub mod secondary_index;
+pub mod transaction;
+pub mod verify;
+pub mod wal;
 
 #[cfg(test)]
 mod tests;
@@ -44,7 +67,8 @@ impl std::fmt::Display for Idx {
 }
 
 #[repr(transparent)]
-#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Key {
     pub val: u32,
 }
@@ -76,16 +100,16 @@ impl std::fmt::Display for Key {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum IdxOrKey {
+pub enum IdxOrKey<K = Key> {
     Idx(Idx),
-    Key(Key),
+    Key(K),
 }
 
-impl std::fmt::Debug for IdxOrKey {
+impl<K: std::fmt::Debug> std::fmt::Debug for IdxOrKey<K> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             IdxOrKey::Idx(idx) => write!(f, "IdxOrKey::Idx({})", idx.val),
-            IdxOrKey::Key(key) => write!(f, "IdxOrKey::Key({:#010x})", key.val),
+            IdxOrKey::Key(key) => write!(f, "IdxOrKey::Key({:?})", key),
         }
     }
 }
@@ -93,7 +117,7 @@ impl std::fmt::Debug for IdxOrKey {
 pub type Relationships<T> = UnGraphMap<Key, T>;
 
 #[repr(transparent)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct BookId {
     pub val: u64,
 }
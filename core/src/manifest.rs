@@ -0,0 +1,76 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::options::BookOptions;
+
+pub const MANIFEST_FORMAT_VERSION: u32 = 1;
+
+/// Creation-time properties of a book and the unique constraints declared on
+/// it, persisted as `books/<id>/manifest`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookManifest {
+    pub version: u32,
+    pub page_size: usize,
+    /// Names of the unique constraints that must be registered before the book accepts writes.
+    #[serde(default)]
+    pub unique_constraints: BTreeSet<String>,
+}
+
+impl BookManifest {
+    pub fn new(options: &BookOptions) -> Self {
+        Self {
+            version: MANIFEST_FORMAT_VERSION,
+            page_size: options.page_size,
+            unique_constraints: BTreeSet::new(),
+        }
+    }
+
+    #[inline]
+    pub fn path(book_dir: &Path) -> PathBuf {
+        book_dir.join("manifest")
+    }
+
+    /// Load the manifest of the book in `book_dir`, if it has one.
+    pub fn load(book_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(book_dir);
+
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(anyhow::anyhow!(e).context(format!("failed to read {:?}", path))),
+        };
+
+        let manifest: Self = serde_json::from_slice(&content)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to parse {:?}", path)))?;
+
+        if manifest.version != MANIFEST_FORMAT_VERSION {
+            anyhow::bail!(
+                "manifest {:?} has version {}, expected {}",
+                path,
+                manifest.version,
+                MANIFEST_FORMAT_VERSION
+            );
+        }
+
+        Ok(Some(manifest))
+    }
+
+    /// Write the manifest via a temporary file so a crash never leaves it half written.
+    pub fn store(&self, book_dir: &Path) -> anyhow::Result<()> {
+        let path = Self::path(book_dir);
+        let tmp_path = book_dir.join("manifest.tmp");
+
+        fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", tmp_path)))?;
+        fs::File::open(&tmp_path)?.sync_all()?;
+        fs::rename(&tmp_path, &path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", path)))?;
+
+        Ok(())
+    }
+}
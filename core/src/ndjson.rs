@@ -0,0 +1,164 @@
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{book::Book, book_inner::BatchOutcome, book_key::BookKey, persistable::Persistable};
+
+/// Entries an import parses before writing them as one batch.
+const IMPORT_BATCH: usize = 1024;
+
+/// One line of an NDJSON export.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Line<K, T> {
+    key: K,
+    value: T,
+}
+
+/// What `Book::import_ndjson` does with an entry whose key is already taken,
+/// or that a unique index rejects.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Keep what the book holds and move on.
+    Skip,
+    /// Overwrite the existing entry. Unique index conflicts still fail.
+    Replace,
+    /// Stop the import with an error, keeping only the entries before the offending line.
+    #[default]
+    Fail,
+}
+
+/// How many entries an import wrote, and how.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImportReport {
+    pub inserted: usize,
+    pub replaced: usize,
+    pub skipped: usize,
+}
+
+/// Write every entry of `book` to `writer`, one `{"key": ..., "value": ...}`
+/// object per line in key order. Returns the number of entries written.
+pub fn export<T, K>(book: &Book<T, K>, writer: impl Write) -> anyhow::Result<usize>
+where
+    T: Persistable + Serialize,
+    K: BookKey + Serialize,
+{
+    let mut writer = BufWriter::new(writer);
+    let mut count = 0;
+
+    for entry in book.range(..) {
+        let (key, value) = entry?;
+
+        serde_json::to_writer(&mut writer, &Line { key, value })?;
+        writer.write_all(b"\n")?;
+        count += 1;
+    }
+
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Read entries written by `export` from `reader` into `book`, in batches.
+pub fn import<T, K>(
+    book: &Book<T, K>,
+    reader: impl Read,
+    on_conflict: OnConflict,
+) -> anyhow::Result<ImportReport>
+where
+    T: Persistable + DeserializeOwned,
+    K: BookKey + DeserializeOwned,
+{
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(IMPORT_BATCH);
+
+    for (n, line) in BufReader::new(reader).lines().enumerate() {
+        let line_no = n + 1;
+        let line = line
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to read line {}", line_no)))?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let Line { key, value } = serde_json::from_str::<Line<K, T>>(&line)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to parse line {}", line_no)))?;
+
+        batch.push((line_no, key, value));
+
+        if batch.len() == IMPORT_BATCH {
+            write_batch(book, &mut batch, on_conflict, &mut report)?;
+        }
+    }
+
+    write_batch(book, &mut batch, on_conflict, &mut report)?;
+
+    Ok(report)
+}
+
+fn write_batch<T: Persistable, K: BookKey>(
+    book: &Book<T, K>,
+    batch: &mut Vec<(usize, K, T)>,
+    on_conflict: OnConflict,
+    report: &mut ImportReport,
+) -> anyhow::Result<()> {
+    let items = batch.iter().map(|(_, key, value)| (*key, *value));
+
+    let outcomes = if on_conflict == OnConflict::Fail {
+        insert_until_conflict(book, items)?
+    } else {
+        book.insert_many(items)?
+    };
+
+    let mut replace = vec![];
+
+    for ((line_no, key, value), outcome) in batch.drain(..).zip(outcomes) {
+        match (outcome, on_conflict) {
+            (BatchOutcome::Applied, _) => report.inserted += 1,
+            (BatchOutcome::KeyExists | BatchOutcome::Violation(_), OnConflict::Skip) => {
+                report.skipped += 1
+            }
+            (BatchOutcome::KeyExists, OnConflict::Replace) => replace.push((key, value)),
+            (BatchOutcome::KeyExists, OnConflict::Fail) => {
+                anyhow::bail!("line {}: key {:?} already exists", line_no, key)
+            }
+            (BatchOutcome::Violation(violation), _) => {
+                return Err(anyhow::Error::new(violation).context(format!("line {}", line_no)))
+            }
+            (BatchOutcome::KeyNotFound, _) => unreachable!("inserts never miss a key"),
+        }
+    }
+
+    if !replace.is_empty() {
+        book.transaction(|tx| {
+            for (key, value) in &replace {
+                tx.upsert(*key, *value)?;
+            }
+
+            Ok(())
+        })?;
+
+        report.replaced += replace.len();
+    }
+
+    Ok(())
+}
+
+/// Insert only the items before the first one that would conflict, so that a
+/// failing import stops exactly at the offending line. Returns the outcome
+/// each item would have had on its own.
+fn insert_until_conflict<T: Persistable, K: BookKey>(
+    book: &Book<T, K>,
+    items: impl Iterator<Item = (K, T)> + Clone,
+) -> anyhow::Result<Vec<BatchOutcome<K>>> {
+    let mut book_guard = book.write();
+
+    let outcomes = book_guard.check_insert_many(items.clone());
+    let clean = outcomes
+        .iter()
+        .take_while(|outcome| outcome.is_applied())
+        .count();
+
+    book_guard.insert_many(items.take(clean))?;
+
+    Ok(outcomes)
+}
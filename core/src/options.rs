@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+
+use crate::page_layout::DEFAULT_PAGE_SIZE;
+
+pub const DEFAULT_PAGE_CACHE_PAGES: usize = 256;
+pub const DEFAULT_CHANGE_BUFFER: usize = 1024;
+
+/// When a book forces its write-ahead log and pages to stable storage.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncPolicy {
+    /// Never sync implicitly; durability is left to the kernel, `Book::flush` and drop.
+    Never,
+    /// Sync the log and flush every dirty page after each write.
+    EveryWrite,
+    /// Checkpoint at most once per interval, piggybacking on writes.
+    Interval(Duration),
+    /// Sync the log when a write commits; pages are flushed at checkpoints.
+    #[default]
+    OnCommit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Builder, Serialize, Deserialize)]
+#[builder(default)]
+#[serde(default)]
+pub struct BookOptions {
+    pub sync_policy: SyncPolicy,
+    /// Size of each page file in bytes. Fixed when the book is created; the
+    /// value recorded in the book's manifest wins when reopening.
+    pub page_size: usize,
+    /// How many pages the book keeps mapped at once, unless more are pinned.
+    pub page_cache_pages: usize,
+    /// How many changes each subscriber may fall behind before it is dropped,
+    /// and how many recent ones are kept for subscribers resuming from a sequence number.
+    pub change_buffer: usize,
+}
+
+impl Default for BookOptions {
+    fn default() -> Self {
+        Self {
+            sync_policy: SyncPolicy::default(),
+            page_size: DEFAULT_PAGE_SIZE,
+            page_cache_pages: DEFAULT_PAGE_CACHE_PAGES,
+            change_buffer: DEFAULT_CHANGE_BUFFER,
+        }
+    }
+}
@@ -2,30 +2,40 @@ use std::{fs::File, sync::Arc};
 
 use parking_lot::{ArcRwLockUpgradableReadGuard, ArcRwLockWriteGuard, RawRwLock, RwLock};
 
-use crate::page_inner::PageInner;
+use crate::{book_key::BookKey, page_inner::PageInner, persistable::Persistable, Key};
 
 #[derive(Debug)]
-pub struct Page<T>(Arc<RwLock<PageInner<T>>>);
+pub struct Page<T: Persistable, K: BookKey = Key>(Arc<RwLock<PageInner<T, K>>>);
 
-impl<T> Page<T> {
-    pub fn new(file: &File) -> anyhow::Result<Self> {
-        Ok(Page(Arc::new(RwLock::new(PageInner::new(file)?))))
+impl<T: Persistable, K: BookKey> Page<T, K> {
+    pub fn new(file: &File, page_size: usize) -> anyhow::Result<Self> {
+        Ok(Page(Arc::new(RwLock::new(PageInner::new(
+            file, page_size,
+        )?))))
     }
 
-    pub fn parse(file: &File) -> anyhow::Result<Self> {
-        Ok(Page(Arc::new(RwLock::new(PageInner::parse(file)?))))
+    pub fn parse(file: &File, page_size: usize) -> anyhow::Result<Self> {
+        Ok(Page(Arc::new(RwLock::new(PageInner::parse(
+            file, page_size,
+        )?))))
     }
 
-    pub fn read(&self) -> ArcRwLockUpgradableReadGuard<RawRwLock, PageInner<T>> {
+    pub fn read(&self) -> ArcRwLockUpgradableReadGuard<RawRwLock, PageInner<T, K>> {
         self.0.upgradable_read_arc()
     }
 
-    pub fn write(&self) -> ArcRwLockWriteGuard<RawRwLock, PageInner<T>> {
+    pub fn write(&self) -> ArcRwLockWriteGuard<RawRwLock, PageInner<T, K>> {
         self.0.write_arc()
     }
+
+    /// Whether any handle or guard besides this one is alive.
+    #[inline]
+    pub fn is_pinned(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
 }
 
-impl<T> Clone for Page<T> {
+impl<T: Persistable, K: BookKey> Clone for Page<T, K> {
     fn clone(&self) -> Self {
         Page(Arc::clone(&self.0))
     }
@@ -1,17 +1,17 @@
 use std::{mem::MaybeUninit, ptr};
 
-use crate::Key;
+use crate::{book_key::BookKey, persistable::Persistable, Key};
 
 #[repr(C, packed)]
 #[derive(Debug)]
-pub struct PageEntry<T> {
-    key: Key,
+pub struct PageEntry<T: Persistable, K: BookKey = Key> {
+    key: K,
     val: MaybeUninit<T>,
 }
 
-impl<T> PageEntry<T> {
+impl<T: Persistable, K: BookKey> PageEntry<T, K> {
     #[inline]
-    pub fn new(key: Key, val: T) -> Self {
+    pub fn new(key: K, val: T) -> Self {
         Self {
             key,
             val: MaybeUninit::new(val),
@@ -19,31 +19,34 @@ impl<T> PageEntry<T> {
     }
 
     #[inline]
-    pub fn as_ref(ptr: *const Self) -> PageEntryRef<T> {
+    pub fn as_ref(ptr: *const Self) -> PageEntryRef<T, K> {
         PageEntryRef { ptr }
     }
 
     #[inline]
-    pub fn as_mut(ptr: *mut Self) -> PageEntryMut<T> {
+    pub fn as_mut(ptr: *mut Self) -> PageEntryMut<T, K> {
         PageEntryMut { ptr }
     }
 }
 
-impl<T> Default for PageEntry<T> {
+impl<T: Persistable, K: BookKey> Default for PageEntry<T, K> {
     #[inline]
     fn default() -> Self {
         Self {
-            key: Key::default(),
+            key: K::MIN,
             val: MaybeUninit::uninit(),
         }
     }
 }
 
-pub struct PageEntryRef<T> {
-    pub(self) ptr: *const PageEntry<T>,
+pub struct PageEntryRef<T: Persistable, K: BookKey = Key> {
+    pub(self) ptr: *const PageEntry<T, K>,
 }
 
-impl<T> PageEntryRef<T> {
+impl<T: Persistable, K: BookKey> PageEntryRef<T, K> {
+    /// # Safety
+    ///
+    /// The resulting pointer must stay within the page mapping.
     #[inline]
     pub unsafe fn add(&self, offset: usize) -> Self {
         Self {
@@ -52,27 +55,32 @@ impl<T> PageEntryRef<T> {
     }
 
     #[inline]
-    pub fn key(&self) -> Key {
+    pub fn key(&self) -> K {
         unsafe {
             let entry = &*self.ptr;
             ptr::addr_of!(entry.key).read_unaligned()
         }
     }
 
+    /// Read the entry's value. `Persistable` guarantees any bytes in the slot
+    /// form a valid `T`, so this is safe even for a vacant slot.
     #[inline]
-    pub fn val(&mut self) -> MaybeUninit<T> {
+    pub fn val(&self) -> T {
         unsafe {
             let entry = &*self.ptr;
-            ptr::addr_of!(entry.val).read_unaligned()
+            (ptr::addr_of!(entry.val) as *const T).read_unaligned()
         }
     }
 }
 
-pub struct PageEntryMut<T> {
-    pub(self) ptr: *mut PageEntry<T>,
+pub struct PageEntryMut<T: Persistable, K: BookKey = Key> {
+    pub(self) ptr: *mut PageEntry<T, K>,
 }
 
-impl<T> PageEntryMut<T> {
+impl<T: Persistable, K: BookKey> PageEntryMut<T, K> {
+    /// # Safety
+    ///
+    /// The resulting pointer must stay within the page mapping.
     #[inline]
     pub unsafe fn add(&self, offset: usize) -> Self {
         Self {
@@ -81,7 +89,7 @@ impl<T> PageEntryMut<T> {
     }
 
     #[inline]
-    pub fn key(&mut self) -> Key {
+    pub fn key(&mut self) -> K {
         unsafe {
             let entry = &*self.ptr;
             ptr::addr_of!(entry.key).read_unaligned()
@@ -89,7 +97,7 @@ impl<T> PageEntryMut<T> {
     }
 
     #[inline]
-    fn set_key(&mut self, key: Key) {
+    fn set_key(&mut self, key: K) {
         unsafe {
             let entry = &mut *self.ptr;
             ptr::addr_of_mut!(entry.key).write_unaligned(key);
@@ -97,17 +105,19 @@ impl<T> PageEntryMut<T> {
     }
 
     #[inline]
-    pub fn replace_key(&mut self, key: Key) -> Key {
+    pub fn replace_key(&mut self, key: K) -> K {
         let old = self.key();
         self.set_key(key);
         old
     }
 
+    /// Read the entry's value. `Persistable` guarantees any bytes in the slot
+    /// form a valid `T`, so this is safe even for a vacant slot.
     #[inline]
-    pub fn val(&mut self) -> MaybeUninit<T> {
+    pub fn val(&self) -> T {
         unsafe {
             let entry = &*self.ptr;
-            ptr::addr_of!(entry.val).read_unaligned()
+            (ptr::addr_of!(entry.val) as *const T).read_unaligned()
         }
     }
 
@@ -120,7 +130,7 @@ impl<T> PageEntryMut<T> {
     }
 
     #[inline]
-    pub fn replace_val(&mut self, val: T) -> MaybeUninit<T> {
+    pub fn replace_val(&mut self, val: T) -> T {
         let old = self.val();
         self.set_val(val);
         old
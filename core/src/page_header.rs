@@ -0,0 +1,152 @@
+use std::{
+    any::TypeId,
+    mem::{align_of, offset_of, size_of},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{book_key::BookKey, page_entry::PageEntry, persistable::Persistable, Key};
+
+pub const PAGE_MAGIC: [u8; 8] = *b"XDBPAGE\0";
+pub const PAGE_FORMAT_VERSION: u32 = 2;
+
+/// Fixed-size header at the front of every page file, identifying what wrote it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageHeader {
+    pub magic: [u8; 8],
+    pub version: u32,
+    pub page_size: u32,
+    pub entry_size: u32,
+    pub entry_align: u32,
+    pub fingerprint: u64,
+    /// Covers the rest of the page; stamped on every flush.
+    pub checksum: u64,
+}
+
+pub const PAGE_HEADER_BYTES: usize = size_of::<PageHeader>();
+const CHECKSUM_OFFSET: usize = offset_of!(PageHeader, checksum);
+
+impl PageHeader {
+    /// The header a page of `page_size` bytes holding `T` under `K` is expected to carry.
+    pub fn expected<T: Persistable, K: BookKey>(page_size: usize) -> Self {
+        PageHeader {
+            magic: PAGE_MAGIC,
+            version: PAGE_FORMAT_VERSION,
+            page_size: page_size as u32,
+            entry_size: size_of::<PageEntry<T, K>>() as u32,
+            entry_align: align_of::<PageEntry<T, K>>() as u32,
+            fingerprint: entry_fingerprint::<T, K>(),
+            checksum: 0,
+        }
+    }
+
+    pub fn read(file_content: &[u8]) -> anyhow::Result<Self> {
+        if file_content.len() < PAGE_HEADER_BYTES {
+            anyhow::bail!(
+                "page is {} bytes, too short to hold a header",
+                file_content.len()
+            );
+        }
+
+        Ok(unsafe { (file_content.as_ptr() as *const PageHeader).read_unaligned() })
+    }
+
+    /// # Safety
+    ///
+    /// `data_ptr` must point to the start of a writable page mapping.
+    pub unsafe fn write(&self, data_ptr: *mut u8) {
+        (data_ptr as *mut PageHeader).write_unaligned(*self);
+    }
+
+    /// Check that a page read from disk was written for `T` and `K` by this format version.
+    pub fn validate<T: Persistable, K: BookKey>(
+        file_content: &[u8],
+        page_size: usize,
+    ) -> anyhow::Result<()> {
+        let found = Self::read(file_content)?;
+        let expected = Self::expected::<T, K>(page_size);
+
+        if found.magic != expected.magic {
+            anyhow::bail!("page has bad magic {:02x?}, not a page file", found.magic);
+        }
+
+        if found.version != expected.version {
+            anyhow::bail!(
+                "page format version {} is not supported, expected {}",
+                found.version,
+                expected.version
+            );
+        }
+
+        if found.page_size != expected.page_size || file_content.len() != page_size {
+            anyhow::bail!(
+                "page was written with a page size of {} ({} bytes on disk), expected {}",
+                found.page_size,
+                file_content.len(),
+                expected.page_size
+            );
+        }
+
+        if found.entry_size != expected.entry_size || found.entry_align != expected.entry_align {
+            anyhow::bail!(
+                "page holds {}-byte entries aligned to {}, but `{}` keyed by `{}` needs {}-byte entries aligned to {}",
+                found.entry_size,
+                found.entry_align,
+                std::any::type_name::<T>(),
+                std::any::type_name::<K>(),
+                expected.entry_size,
+                expected.entry_align
+            );
+        }
+
+        if found.fingerprint != expected.fingerprint {
+            anyhow::bail!(
+                "page type fingerprint {:#018x} does not match `{}` keyed by `{}` ({:#018x})",
+                found.fingerprint,
+                std::any::type_name::<T>(),
+                std::any::type_name::<K>(),
+                expected.fingerprint
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Hash every byte of a page except the checksum field itself.
+pub fn page_checksum(file_content: &[u8]) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(&file_content[..CHECKSUM_OFFSET]);
+    hasher.update(&file_content[PAGE_HEADER_BYTES..]);
+
+    let digest = hasher.finalize();
+    u64::from_le_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+/// Identify `T` by name and shape. `type_name` is not guaranteed stable across
+/// compiler versions, so a toolchain upgrade may require a migration.
+pub fn type_fingerprint<T: Persistable>() -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(std::any::type_name::<T>().as_bytes());
+    hasher.update((size_of::<T>() as u64).to_le_bytes());
+    hasher.update((align_of::<T>() as u64).to_le_bytes());
+
+    let digest = hasher.finalize();
+    u64::from_le_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+/// Identify the entries of a page holding `T` under `K`. Pages keyed by `Key`
+/// carry the fingerprint of `T` alone, as they did before keys were pluggable.
+pub fn entry_fingerprint<T: Persistable, K: BookKey>() -> u64 {
+    if TypeId::of::<K>() == TypeId::of::<Key>() {
+        return type_fingerprint::<T>();
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(type_fingerprint::<T>().to_le_bytes());
+    hasher.update(type_fingerprint::<K>().to_le_bytes());
+
+    let digest = hasher.finalize();
+    u64::from_le_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
@@ -3,37 +3,52 @@ use std::fs::File;
 use memmap2::MmapMut;
 
 use crate::{
+    book_key::BookKey,
     page_entry::{PageEntryMut, PageEntryRef},
-    page_layout::PAGE_SIZE,
+    page_header::{page_checksum, PageHeader},
     page_meta::PageMeta,
+    persistable::Persistable,
     Idx, IdxOrKey, Key,
 };
 
+/// `(slot, on_page, in_memory)` keys for a slot whose bitmap and metadata disagree.
+pub type SlotMismatch<K = Key> = (Idx, Option<K>, Option<K>);
+
 #[derive(Debug)]
-pub struct PageInner<T> {
+pub struct PageInner<T: Persistable, K: BookKey = Key> {
     data: MmapMut,
-    meta: PageMeta<T>,
+    meta: PageMeta<T, K>,
 }
 
-impl<T> PageInner<T> {
-    /// Create a new empty `PageInner`.
-    pub fn new(file: &File) -> anyhow::Result<Self> {
-        file.set_len(PAGE_SIZE as u64)?;
+impl<T: Persistable, K: BookKey> PageInner<T, K> {
+    /// Create a new empty `PageInner` of `page_size` bytes.
+    pub fn new(file: &File, page_size: usize) -> anyhow::Result<Self> {
+        file.set_len(page_size as u64)?;
 
         let mut data = unsafe { MmapMut::map_mut(file)? };
-        let meta = PageMeta::new();
+        let meta = PageMeta::new(page_size);
 
-        // note: ensure the bitmap is zeroed
+        // note: stamp the header and ensure the bitmap is zeroed
         unsafe {
-            std::ptr::write_bytes(data.as_mut_ptr(), 0, meta.bitmap_bytes);
+            PageHeader::expected::<T, K>(page_size).write(data.as_mut_ptr());
+            std::ptr::write_bytes(
+                data.as_mut_ptr().add(meta.header_bytes),
+                0,
+                meta.bitmap_bytes,
+            );
         }
 
-        Ok(PageInner { data, meta })
+        let mut page = PageInner { data, meta };
+        page.stamp_checksum()?;
+
+        Ok(page)
     }
 
-    /// Parse an existing `PageInner`.
-    pub fn parse(file: &File) -> anyhow::Result<Self> {
+    /// Parse an existing `PageInner`, rejecting pages written for another type or format.
+    pub fn parse(file: &File, page_size: usize) -> anyhow::Result<Self> {
         let data = unsafe { MmapMut::map_mut(file) }?;
+        PageHeader::validate::<T, K>(data.as_ref(), page_size)?;
+
         let meta = PageMeta::parse(data.as_ref())?;
 
         Ok(PageInner { data, meta })
@@ -44,6 +59,11 @@ impl<T> PageInner<T> {
         self.meta.len()
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty()
+    }
+
     #[inline]
     pub fn is_full(&self) -> bool {
         self.meta.is_full()
@@ -55,22 +75,22 @@ impl<T> PageInner<T> {
     }
 
     #[inline]
-    pub fn has_key(&self, key: Key) -> bool {
+    pub fn has_key(&self, key: K) -> bool {
         self.meta.has_key(key)
     }
 
     #[inline]
-    pub fn lookup_key(&self, idx: Idx) -> Option<Key> {
+    pub fn lookup_key(&self, idx: Idx) -> Option<K> {
         self.meta.lookup_key(idx)
     }
 
     #[inline]
-    pub fn lookup_idx(&self, key: Key) -> Option<Idx> {
+    pub fn lookup_idx(&self, key: K) -> Option<Idx> {
         self.meta.lookup_idx(key)
     }
 
     #[inline]
-    pub fn get_by_idx_mut(&mut self, idx: Idx) -> anyhow::Result<PageEntryMut<T>> {
+    pub fn get_by_idx_mut(&mut self, idx: Idx) -> anyhow::Result<PageEntryMut<T, K>> {
         if self.is_idx_vacant(idx) {
             anyhow::bail!("idx is vacant");
         }
@@ -82,7 +102,7 @@ impl<T> PageInner<T> {
     }
 
     #[inline]
-    pub fn get_by_idx(&self, idx: Idx) -> anyhow::Result<PageEntryRef<T>> {
+    pub fn get_by_idx(&self, idx: Idx) -> anyhow::Result<PageEntryRef<T, K>> {
         if self.is_idx_vacant(idx) {
             anyhow::bail!("idx is vacant");
         }
@@ -91,7 +111,7 @@ impl<T> PageInner<T> {
     }
 
     #[inline]
-    pub fn get_by_key_mut(&mut self, key: Key) -> anyhow::Result<PageEntryMut<T>> {
+    pub fn get_by_key_mut(&mut self, key: K) -> anyhow::Result<PageEntryMut<T, K>> {
         let idx = if let Some(idx) = self.meta.lookup_idx(key) {
             idx
         } else {
@@ -105,7 +125,7 @@ impl<T> PageInner<T> {
     }
 
     #[inline]
-    pub fn get_by_key(&self, key: Key) -> anyhow::Result<PageEntryRef<T>> {
+    pub fn get_by_key(&self, key: K) -> anyhow::Result<PageEntryRef<T, K>> {
         let idx = if let Some(idx) = self.meta.lookup_idx(key) {
             idx
         } else {
@@ -116,7 +136,7 @@ impl<T> PageInner<T> {
     }
 
     #[inline]
-    pub fn insert(&mut self, key: Key, val: T) -> anyhow::Result<Option<T>> {
+    pub fn insert(&mut self, key: K, val: T) -> anyhow::Result<Option<T>> {
         if let Some(idx) = self.lookup_idx(key) {
             let mut entry = self
                 .get_by_idx_mut(idx)
@@ -126,7 +146,7 @@ impl<T> PageInner<T> {
                 .replace_key(idx, key)
                 .expect("`idx` is known to be occupied");
 
-            Ok(Some(unsafe { entry.replace_val(val).assume_init() }))
+            Ok(Some(entry.replace_val(val)))
         } else {
             let idx = self.meta.insert_key(key)?;
 
@@ -137,19 +157,115 @@ impl<T> PageInner<T> {
             entry.replace_key(key);
             entry.replace_val(val);
 
+            // note: the bit is set last so a torn write never exposes a half-written slot
+            unsafe {
+                self.meta
+                    .set_nth_vacant(self.data.as_mut_ptr(), idx.as_usize(), false)
+            };
+
             Ok(None)
         }
     }
 
     #[inline]
-    pub fn delete(&mut self, key: Key) -> anyhow::Result<()> {
-        self.meta.vacate(IdxOrKey::Key(key))?;
+    pub fn delete(&mut self, key: K) -> anyhow::Result<()> {
+        let (idx, _) = self.meta.vacate(IdxOrKey::Key(key))?;
+
+        unsafe {
+            self.meta
+                .set_nth_vacant(self.data.as_mut_ptr(), idx.as_usize(), true)
+        };
+
+        Ok(())
+    }
+
+    /// Stamp a fresh checksum and synchronously write the page back to its file.
+    #[inline]
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.stamp_checksum()?;
+        self.data.flush()?;
+
+        Ok(())
+    }
+
+    fn stamp_checksum(&mut self) -> anyhow::Result<()> {
+        let mut header = PageHeader::read(&self.data)?;
+        header.checksum = page_checksum(&self.data);
+
+        unsafe { header.write(self.data.as_mut_ptr()) };
+
+        Ok(())
+    }
+
+    /// The checksum stored at the last flush and the one the page hashes to now.
+    pub fn checksums(&self) -> anyhow::Result<(u64, u64)> {
+        Ok((
+            PageHeader::read(&self.data)?.checksum,
+            page_checksum(&self.data),
+        ))
+    }
+
+    /// Re-read the bitmap and keys from the page bytes, ignoring the in-memory metadata.
+    pub fn parse_meta(&self) -> anyhow::Result<PageMeta<T, K>> {
+        PageMeta::parse(&self.data)
+    }
+
+    /// Slots whose on-page bitmap and key disagree with the in-memory metadata.
+    pub fn meta_mismatches(&self) -> anyhow::Result<Vec<SlotMismatch<K>>> {
+        let parsed = self.parse_meta()?;
+
+        Ok((0..self.meta.cap)
+            .map(|n| Idx::new(n as u32))
+            .filter_map(|idx| {
+                let on_page = parsed.lookup_key(idx);
+                let in_memory = self.meta.lookup_key(idx);
+
+                (on_page != in_memory).then_some((idx, on_page, in_memory))
+            })
+            .collect())
+    }
+
+    /// Start writing the page's dirty bytes back without waiting for completion.
+    #[inline]
+    pub fn flush_async(&self) -> anyhow::Result<()> {
+        self.data.flush_async()?;
 
         Ok(())
     }
 
     #[inline]
-    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
         self.meta.keys()
     }
+
+    /// Read a copy of the value stored under `key`.
+    #[inline]
+    pub fn get(&self, key: K) -> Option<T> {
+        Some(self.get_by_key(key).ok()?.val())
+    }
+
+    /// Find the first occupied slot at or after `from`, returning its entry.
+    #[inline]
+    pub fn next_entry(&self, from: Idx) -> Option<(Idx, K, T)> {
+        (from.as_usize()..self.meta.cap)
+            .map(|n| Idx::new(n as u32))
+            .find(|idx| !self.is_idx_vacant(*idx))
+            .map(|idx| {
+                let entry = self.get_by_idx(idx).expect("`idx` is known to be occupied");
+
+                (idx, entry.key(), entry.val())
+            })
+    }
+
+    /// Apply `f` to the value stored under `key` and write the result back.
+    #[inline]
+    pub fn update<R>(&mut self, key: K, f: impl FnOnce(&mut T) -> R) -> anyhow::Result<R> {
+        let mut entry = self.get_by_key_mut(key)?;
+        let mut val = entry.val();
+
+        let ret = f(&mut val);
+        entry.replace_val(val);
+
+        Ok(ret)
+    }
 }
@@ -4,58 +4,75 @@ use std::{
 };
 
 use crate::{
+    book_key::BookKey,
     page_entry::{PageEntry, PageEntryMut, PageEntryRef},
+    page_header::PAGE_HEADER_BYTES,
+    persistable::Persistable,
     Idx, Key,
 };
 
-// 1MB page size
-pub const PAGE_SIZE: usize = 128; //1024 * 1024;
+// 1MB page size, used when a book does not choose its own
+pub const DEFAULT_PAGE_SIZE: usize = 1024 * 1024;
 
 #[derive(Debug, PartialEq)]
-pub struct PageLayout<T> {
+pub struct PageLayout<T: Persistable, K: BookKey = Key> {
     pub cap: usize,
     pub elem_layout: Layout,
     pub total_usage: usize,
+    pub header_bytes: usize,
     pub bitmap_bytes: usize,
+    pub array_start: usize,
     pub wasted_bytes: usize,
-    _marker: std::marker::PhantomData<T>,
+    _marker: std::marker::PhantomData<(T, K)>,
 }
 
-impl<T> Clone for PageLayout<T> {
+impl<T: Persistable, K: BookKey> Clone for PageLayout<T, K> {
     fn clone(&self) -> Self {
         *self
     }
 }
 
-impl<T> Copy for PageLayout<T> {}
+impl<T: Persistable, K: BookKey> Copy for PageLayout<T, K> {}
 
-impl<T> PageLayout<T> {
-    pub fn new() -> Self {
-        let total_memory: usize = PAGE_SIZE;
-        let align = align_of::<PageEntry<T>>();
-        let size = size_of::<PageEntry<T>>();
+impl<T: Persistable, K: BookKey> Default for PageLayout<T, K> {
+    fn default() -> Self {
+        Self::new(DEFAULT_PAGE_SIZE)
+    }
+}
+
+impl<T: Persistable, K: BookKey> PageLayout<T, K> {
+    pub fn new(page_size: usize) -> Self {
+        let total_memory: usize = page_size;
+        let align = align_of::<PageEntry<T, K>>();
+        let size = size_of::<PageEntry<T, K>>();
+
+        // The header always comes first, the bitmap directly after it
+        let header_bytes = PAGE_HEADER_BYTES;
 
         // We need to find the maximum number of elements `cap` that fit in memory
-        let mut cap = (total_memory - 1) / size; // start with an upper bound guess
+        let mut cap = total_memory.saturating_sub(header_bytes + 1) / size; // start with an upper bound guess
 
         loop {
             // Calculate the number of bytes required for the bitmap
-            let bitmap_bytes = (cap + 7) / 8;
+            let bitmap_bytes = cap.div_ceil(8);
 
             // Calculate the first aligned address after the bitmap
-            let array_start = (bitmap_bytes + align - 1) & !(align - 1); // aligned offset
+            let array_start = (header_bytes + bitmap_bytes + align - 1) & !(align - 1); // aligned offset
 
-            // Total memory usage: bitmap + array (cap elements)
+            // Total memory usage: header + bitmap + array (cap elements)
             let total_usage = array_start + cap * size;
 
-            if total_usage <= total_memory {
+            // note: a page too small for even the header ends up with no capacity
+            if total_usage <= total_memory || cap == 0 {
                 // We've found the valid `cap` that fits
                 return PageLayout {
                     cap,
                     elem_layout: Layout::from_size_align(size, align).expect("Invalid layout"),
                     total_usage,
+                    header_bytes,
                     bitmap_bytes,
-                    wasted_bytes: total_memory - total_usage,
+                    array_start,
+                    wasted_bytes: total_memory.saturating_sub(total_usage),
                     _marker: std::marker::PhantomData,
                 };
             }
@@ -65,65 +82,86 @@ impl<T> PageLayout<T> {
         }
     }
 
+    /// # Safety
+    ///
+    /// `data_ptr` must point to the start of a page mapping of at least `total_usage` bytes.
     #[inline]
-    pub unsafe fn array_ptr_mut(&self, data_ptr: *mut u8) -> PageEntryMut<T> {
-        let bitmap_bytes = (self.cap + 7) / 8;
-        let array_start =
-            (bitmap_bytes + self.elem_layout.align() - 1) & !(self.elem_layout.align() - 1);
-
-        PageEntry::as_mut(data_ptr.add(array_start) as *mut _)
+    pub unsafe fn array_ptr_mut(&self, data_ptr: *mut u8) -> PageEntryMut<T, K> {
+        PageEntry::as_mut(data_ptr.add(self.array_start) as *mut _)
     }
 
+    /// # Safety
+    ///
+    /// `data_ptr` must point to the start of a page mapping of at least `total_usage` bytes.
     #[inline]
-    pub unsafe fn array_ptr(&self, data_ptr: *const u8) -> PageEntryRef<T> {
-        let bitmap_bytes = (self.cap + 7) / 8;
-        let array_start =
-            (bitmap_bytes + self.elem_layout.align() - 1) & !(self.elem_layout.align() - 1);
-
-        PageEntry::as_ref(data_ptr.add(array_start) as *const _)
+    pub unsafe fn array_ptr(&self, data_ptr: *const u8) -> PageEntryRef<T, K> {
+        PageEntry::as_ref(data_ptr.add(self.array_start) as *const _)
     }
 
+    /// # Safety
+    ///
+    /// `data_ptr` must point to the start of a page mapping and `n` must be less than `cap`.
     #[inline]
-    pub unsafe fn nth_ptr_mut(&self, data_ptr: *mut u8, n: usize) -> PageEntryMut<T> {
-        self.array_ptr_mut(data_ptr)
-            .add(n * self.elem_layout.size())
+    pub unsafe fn nth_ptr_mut(&self, data_ptr: *mut u8, n: usize) -> PageEntryMut<T, K> {
+        // note: `add` steps in whole entries, not bytes
+        self.array_ptr_mut(data_ptr).add(n)
     }
 
+    /// # Safety
+    ///
+    /// `data_ptr` must point to the start of a page mapping and `n` must be less than `cap`.
     #[inline]
-    pub unsafe fn nth_ptr(&self, data_ptr: *const u8, n: usize) -> PageEntryRef<T> {
-        self.array_ptr(data_ptr).add(n * self.elem_layout.size())
+    pub unsafe fn nth_ptr(&self, data_ptr: *const u8, n: usize) -> PageEntryRef<T, K> {
+        self.array_ptr(data_ptr).add(n)
     }
 
+    /// # Safety
+    ///
+    /// `data_ptr` must point to the start of a page mapping and `n` must be less than `cap`.
     #[inline]
     pub unsafe fn nth_is_vacant(&self, data_ptr: *const u8, n: usize) -> bool {
         let byte = n / 8;
         let bit = n % 8;
         let mask = 1 << bit;
-        if *data_ptr.add(byte) & mask != 0 {
-            false
+        *data_ptr.add(self.header_bytes + byte) & mask == 0
+    }
+
+    /// # Safety
+    ///
+    /// `data_ptr` must point to the start of a writable page mapping and `n` must be less than `cap`.
+    #[inline]
+    pub unsafe fn set_nth_vacant(&self, data_ptr: *mut u8, n: usize, vacant: bool) {
+        let byte = data_ptr.add(self.header_bytes + n / 8);
+        let mask = 1 << (n % 8);
+
+        if vacant {
+            *byte &= !mask;
         } else {
-            true
+            *byte |= mask;
         }
     }
 
+    /// # Safety
+    ///
+    /// `file_content` must be a complete page laid out according to `self`.
     #[inline]
-    pub unsafe fn page_entry_iter<'a, 'b>(
-        &'a self,
+    pub unsafe fn page_entry_iter<'b>(
+        &self,
         file_content: &'b [u8],
-    ) -> anyhow::Result<PageEntryIter<'b, T>> {
+    ) -> anyhow::Result<PageEntryIter<'b, T, K>> {
         PageEntryIter::new(file_content, *self)
     }
 }
 
-pub struct PageEntryIter<'a, T> {
+pub struct PageEntryIter<'a, T: Persistable, K: BookKey = Key> {
     data: &'a [u8],
     step: usize,
-    layout: PageLayout<T>,
+    layout: PageLayout<T, K>,
     error: Option<anyhow::Error>,
 }
 
-impl<'a, T> PageEntryIter<'a, T> {
-    pub fn new(data: &'a [u8], layout: PageLayout<T>) -> anyhow::Result<Self> {
+impl<'a, T: Persistable, K: BookKey> PageEntryIter<'a, T, K> {
+    pub fn new(data: &'a [u8], layout: PageLayout<T, K>) -> anyhow::Result<Self> {
         Ok(Self {
             data,
             step: 0,
@@ -133,8 +171,8 @@ impl<'a, T> PageEntryIter<'a, T> {
     }
 }
 
-impl<'a, T> Iterator for PageEntryIter<'a, T> {
-    type Item = (Idx, Option<Key>);
+impl<'a, T: Persistable, K: BookKey> Iterator for PageEntryIter<'a, T, K> {
+    type Item = (Idx, Option<K>);
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.error.is_some() {
@@ -144,23 +182,23 @@ impl<'a, T> Iterator for PageEntryIter<'a, T> {
         let len = self.layout.cap;
         let data_ptr = self.data.as_ptr();
 
-        while self.step < len {
-            let step = self.step;
-            self.step += 1;
+        if self.step >= len {
+            return None;
+        }
+
+        let step = self.step;
+        self.step += 1;
 
-            let idx = Idx::new((step) as u32);
-            let vacant = unsafe { self.layout.nth_is_vacant(data_ptr, step) };
+        let idx = Idx::new((step) as u32);
+        let vacant = unsafe { self.layout.nth_is_vacant(data_ptr, step) };
 
-            if vacant {
-                return Some((idx, None));
-            } else {
-                return Some((
-                    idx,
-                    Some(unsafe { self.layout.nth_ptr(data_ptr, step).key() }),
-                ));
-            }
+        if vacant {
+            Some((idx, None))
+        } else {
+            Some((
+                idx,
+                Some(unsafe { self.layout.nth_ptr(data_ptr, step).key() }),
+            ))
         }
-
-        None
     }
 }
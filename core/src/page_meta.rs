@@ -1,18 +1,29 @@
 use std::collections::{BTreeSet, HashMap};
 
-use crate::{page_layout::PageLayout, Idx, IdxOrKey, Key};
+use crate::{
+    book_key::BookKey,
+    page_layout::{PageLayout, DEFAULT_PAGE_SIZE},
+    persistable::Persistable,
+    Idx, IdxOrKey, Key,
+};
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct PageMeta<T> {
-    layout: PageLayout<T>,
-    idx_to_key: HashMap<Idx, Key>,
-    key_to_idx: HashMap<Key, Idx>,
+pub struct PageMeta<T: Persistable, K: BookKey = Key> {
+    layout: PageLayout<T, K>,
+    idx_to_key: HashMap<Idx, K>,
+    key_to_idx: HashMap<K, Idx>,
     vacant_idx: BTreeSet<Idx>,
 }
 
-impl<T> PageMeta<T> {
-    pub fn new() -> Self {
-        let layout = PageLayout::new();
+impl<T: Persistable, K: BookKey> Default for PageMeta<T, K> {
+    fn default() -> Self {
+        Self::new(DEFAULT_PAGE_SIZE)
+    }
+}
+
+impl<T: Persistable, K: BookKey> PageMeta<T, K> {
+    pub fn new(page_size: usize) -> Self {
+        let layout = PageLayout::new(page_size);
         let cap = layout.cap;
 
         PageMeta {
@@ -24,7 +35,7 @@ impl<T> PageMeta<T> {
     }
 
     pub fn parse(file_content: &[u8]) -> anyhow::Result<Self> {
-        let layout = PageLayout::new();
+        let layout = PageLayout::new(file_content.len());
         let cap = layout.cap;
 
         let mut idx_to_key = HashMap::with_capacity(cap);
@@ -53,6 +64,11 @@ impl<T> PageMeta<T> {
         self.idx_to_key.len()
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.idx_to_key.is_empty()
+    }
+
     #[inline]
     pub fn is_full(&self) -> bool {
         self.len() == self.layout.cap
@@ -64,29 +80,29 @@ impl<T> PageMeta<T> {
     }
 
     #[inline]
-    pub fn has_key(&self, key: Key) -> bool {
+    pub fn has_key(&self, key: K) -> bool {
         self.key_to_idx.contains_key(&key)
     }
 
     #[inline]
-    pub fn lookup_key(&self, idx: Idx) -> Option<Key> {
+    pub fn lookup_key(&self, idx: Idx) -> Option<K> {
         self.idx_to_key.get(&idx).copied()
     }
 
     #[inline]
-    pub fn lookup_idx(&self, key: Key) -> Option<Idx> {
+    pub fn lookup_idx(&self, key: K) -> Option<Idx> {
         self.key_to_idx.get(&key).copied()
     }
 
     #[inline]
-    pub fn vacate(&mut self, idx_or_key: IdxOrKey) -> anyhow::Result<(Idx, Key)> {
+    pub fn vacate(&mut self, idx_or_key: IdxOrKey<K>) -> anyhow::Result<(Idx, K)> {
         match idx_or_key {
             IdxOrKey::Idx(idx) => {
                 if !self.idx_to_key.contains_key(&idx) {
                     anyhow::bail!("Slot is already vacant");
                 }
 
-                let key = self.idx_to_key.remove(&idx).expect("Key not found");
+                let key = self.idx_to_key.remove(&idx).expect("K not found");
                 self.key_to_idx.remove(&key).expect("Index not found");
                 self.vacant_idx.insert(idx);
 
@@ -94,11 +110,11 @@ impl<T> PageMeta<T> {
             }
             IdxOrKey::Key(key) => {
                 if !self.key_to_idx.contains_key(&key) {
-                    anyhow::bail!("Key not found");
+                    anyhow::bail!("K not found");
                 }
 
                 let idx = self.key_to_idx.remove(&key).expect("Index not found");
-                self.idx_to_key.remove(&idx).expect("Key not found");
+                self.idx_to_key.remove(&idx).expect("K not found");
                 self.vacant_idx.insert(idx);
 
                 Ok((idx, key))
@@ -107,7 +123,7 @@ impl<T> PageMeta<T> {
     }
 
     #[inline]
-    pub fn insert_idx_and_key(&mut self, idx: Idx, key: Key) -> anyhow::Result<()> {
+    pub fn insert_idx_and_key(&mut self, idx: Idx, key: K) -> anyhow::Result<()> {
         if !self.is_idx_vacant(idx) {
             anyhow::bail!("Slot is already occupied");
         }
@@ -117,17 +133,20 @@ impl<T> PageMeta<T> {
         Ok(())
     }
 
+    /// # Safety
+    ///
+    /// `idx` must be vacant and `key` must not already be present in the page.
     #[inline(always)]
-    pub unsafe fn insert_idx_and_key_unchecked(&mut self, idx: Idx, key: Key) {
+    pub unsafe fn insert_idx_and_key_unchecked(&mut self, idx: Idx, key: K) {
         self.idx_to_key.insert(idx, key);
         self.key_to_idx.insert(key, idx);
         self.vacant_idx.remove(&idx);
     }
 
     #[inline]
-    pub fn insert_key(&mut self, key: Key) -> anyhow::Result<Idx> {
+    pub fn insert_key(&mut self, key: K) -> anyhow::Result<Idx> {
         if self.has_key(key) {
-            anyhow::bail!("Key already exists");
+            anyhow::bail!("K already exists");
         }
 
         let idx = if let Some(idx) = self.vacant_idx.iter().next().copied() {
@@ -144,26 +163,26 @@ impl<T> PageMeta<T> {
     }
 
     #[inline]
-    pub fn replace_key(&mut self, idx: Idx, key: Key) -> anyhow::Result<Key> {
+    pub fn replace_key(&mut self, idx: Idx, key: K) -> anyhow::Result<K> {
         if self.is_idx_vacant(idx) {
             anyhow::bail!("Slot is vacant");
         }
 
-        let old_key = self.idx_to_key.insert(idx, key).expect("Key not found");
+        let old_key = self.idx_to_key.insert(idx, key).expect("K not found");
+        self.key_to_idx.remove(&old_key).expect("K not found");
         self.key_to_idx.insert(key, idx);
-        self.key_to_idx.remove(&old_key).expect("Key not found");
 
         Ok(old_key)
     }
 
     #[inline]
-    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
         self.key_to_idx.keys()
     }
 }
 
-impl<T> std::ops::Deref for PageMeta<T> {
-    type Target = PageLayout<T>;
+impl<T: Persistable, K: BookKey> std::ops::Deref for PageMeta<T, K> {
+    type Target = PageLayout<T, K>;
 
     fn deref(&self) -> &Self::Target {
         &self.layout
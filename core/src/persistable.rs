@@ -0,0 +1,53 @@
+pub use experimental_db_derive::Persistable;
+
+use crate::{
+    heap::{HeapRef, HeapStr, HeapVec},
+    BookId, Idx, Key,
+};
+
+/// Values that can be copied bytewise into a page file and read back later.
+///
+/// # Safety
+///
+/// Implementors must be plain old data: `Copy`, free of pointers, references
+/// and padding (and therefore trivially `Send` and `Sync`), and valid for every
+/// byte pattern. Values are read back from page files, the write-ahead log and
+/// the heap without validation, so a type like `bool` or `char`, for which a
+/// single corrupted byte is undefined behavior, must not implement it. Use
+/// `#[derive(Persistable)]` on `#[repr(C)]` structs to have this checked.
+///
+/// ```compile_fail
+/// use experimental_db_core::persistable::Persistable;
+///
+/// #[repr(C)]
+/// #[derive(Clone, Copy, Persistable)]
+/// struct Padded {
+///     flag: u8,
+///     count: u32,
+/// }
+/// ```
+///
+/// ```compile_fail
+/// use experimental_db_core::persistable::Persistable;
+///
+/// #[repr(C)]
+/// #[derive(Clone, Copy, Persistable)]
+/// struct Flagged {
+///     flags: [bool; 8],
+/// }
+/// ```
+pub unsafe trait Persistable: Copy + Send + Sync + 'static {}
+
+macro_rules! impl_persistable {
+    ($($ty:ty),* $(,)?) => {
+        $(unsafe impl Persistable for $ty {})*
+    };
+}
+
+impl_persistable!(
+    u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64, Key, Idx, BookId, HeapRef, HeapStr,
+);
+
+unsafe impl<T: Persistable, const N: usize> Persistable for [T; N] {}
+
+unsafe impl<V: Persistable> Persistable for HeapVec<V> {}
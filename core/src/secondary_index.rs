@@ -0,0 +1,454 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, fs,
+    mem::size_of,
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{book_key::BookKey, persistable::Persistable, Key};
+
+pub const SECONDARY_INDEX_MAGIC: [u8; 8] = *b"XDBINDX\0";
+pub const SECONDARY_INDEX_FORMAT_VERSION: u32 = 1;
+
+/// `[magic][version: u32][unique: u32][count: u64]` ahead of the sorted entries,
+/// each `[len: u32][index key: len bytes][key]`.
+const HEADER_BYTES: usize = 24;
+
+/// A value extracted from an entry to index it by. Compares bytewise, and the
+/// conversions from integers encode them so that this order matches theirs.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IndexKey(Vec<u8>);
+
+impl IndexKey {
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for IndexKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match std::str::from_utf8(&self.0) {
+            Ok(val) if !val.is_empty() && !val.contains(char::is_control) => {
+                write!(f, "IndexKey({:?})", val)
+            }
+            _ => {
+                write!(f, "IndexKey(0x")?;
+
+                for byte in &self.0 {
+                    write!(f, "{:02x}", byte)?;
+                }
+
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+macro_rules! impl_index_key_unsigned {
+    ($($ty:ty),* $(,)?) => {
+        $(impl From<$ty> for IndexKey {
+            fn from(val: $ty) -> Self {
+                IndexKey(val.to_be_bytes().to_vec())
+            }
+        })*
+    };
+}
+
+macro_rules! impl_index_key_signed {
+    ($($ty:ty => $unsigned:ty),* $(,)?) => {
+        $(impl From<$ty> for IndexKey {
+            fn from(val: $ty) -> Self {
+                // note: flipping the sign bit sorts negative values before positive ones
+                IndexKey(((val as $unsigned) ^ (1 << (<$ty>::BITS - 1))).to_be_bytes().to_vec())
+            }
+        })*
+    };
+}
+
+impl_index_key_unsigned!(u8, u16, u32, u64, u128);
+impl_index_key_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+impl From<Key> for IndexKey {
+    fn from(key: Key) -> Self {
+        key.val.into()
+    }
+}
+
+impl From<&str> for IndexKey {
+    fn from(val: &str) -> Self {
+        IndexKey(val.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for IndexKey {
+    fn from(val: String) -> Self {
+        IndexKey(val.into_bytes())
+    }
+}
+
+impl From<&[u8]> for IndexKey {
+    fn from(val: &[u8]) -> Self {
+        IndexKey(val.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for IndexKey {
+    fn from(val: Vec<u8>) -> Self {
+        IndexKey(val)
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for IndexKey {
+    fn from(val: [u8; N]) -> Self {
+        IndexKey(val.to_vec())
+    }
+}
+
+/// A write rejected because it would give two entries the same value in a
+/// unique index. Returned inside `anyhow::Error`; use `downcast_ref` to match it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstraintViolation<K = Key> {
+    /// The unique index, or constraint, that was violated.
+    pub constraint: String,
+    pub value: IndexKey,
+    /// The key being written.
+    pub key: K,
+    /// The key that already holds, or is being given, the same value.
+    pub conflicting: K,
+}
+
+impl<K: fmt::Debug> fmt::Display for ConstraintViolation<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unique constraint {:?} violated: key {:?} would share {:?} with key {:?}",
+            self.constraint, self.key, self.value, self.conflicting
+        )
+    }
+}
+
+impl<K: fmt::Debug> std::error::Error for ConstraintViolation<K> {}
+
+/// Derives the `IndexKey` an entry is indexed under.
+pub type IndexExtractor<T> = Arc<dyn Fn(&T) -> IndexKey + Send + Sync>;
+
+/// An ordered map from an extracted field to the keys of the entries holding it.
+///
+/// The extractor is code and cannot be persisted, so an index only exists while
+/// it is registered with `BookInner::create_index`. Its entries are stored as
+/// `books/<id>/indexes/<name>` at every checkpoint and picked up again by the
+/// next registration under the same name, as long as the book has not changed
+/// in the meantime.
+pub struct SecondaryIndex<T: Persistable, K: BookKey = Key> {
+    name: String,
+    unique: bool,
+    extractor: IndexExtractor<T>,
+    entries: BTreeSet<(IndexKey, K)>,
+    changed: bool,
+}
+
+impl<T: Persistable, K: BookKey> fmt::Debug for SecondaryIndex<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecondaryIndex")
+            .field("name", &self.name)
+            .field("unique", &self.unique)
+            .field("len", &self.entries.len())
+            .field("changed", &self.changed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Persistable, K: BookKey> SecondaryIndex<T, K> {
+    pub fn new(name: &str, unique: bool, extractor: IndexExtractor<T>) -> Self {
+        SecondaryIndex {
+            name: name.to_string(),
+            unique,
+            extractor,
+            entries: BTreeSet::new(),
+            changed: true,
+        }
+    }
+
+    #[inline]
+    pub fn dir(book_dir: &Path) -> PathBuf {
+        book_dir.join("indexes")
+    }
+
+    #[inline]
+    pub fn path(book_dir: &Path, name: &str) -> PathBuf {
+        Self::dir(book_dir).join(name)
+    }
+
+    /// Index names double as file names, so they are kept to a safe alphabet.
+    pub fn validate_name(name: &str) -> anyhow::Result<()> {
+        let valid = !name.is_empty()
+            && !name.ends_with(".tmp")
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+            && !name.starts_with('.');
+
+        if !valid {
+            anyhow::bail!("invalid index name {:?}", name);
+        }
+
+        Ok(())
+    }
+
+    /// Load the stored entries of the index `name`, if there are any and they
+    /// were stored with the same uniqueness.
+    pub fn load(
+        book_dir: &Path,
+        name: &str,
+        unique: bool,
+        extractor: IndexExtractor<T>,
+    ) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(book_dir, name);
+
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(anyhow::anyhow!(e).context(format!("failed to read {:?}", path))),
+        };
+
+        if content.len() < HEADER_BYTES
+            || content[..8] != SECONDARY_INDEX_MAGIC
+            || u32::from_le_bytes(content[8..12].try_into()?) != SECONDARY_INDEX_FORMAT_VERSION
+            || u32::from_le_bytes(content[12..16].try_into()?) != unique as u32
+        {
+            return Ok(None);
+        }
+
+        let count = u64::from_le_bytes(content[16..24].try_into()?) as usize;
+        let mut index = Self::new(name, unique, extractor);
+        let mut rest = &content[HEADER_BYTES..];
+
+        for _ in 0..count {
+            if rest.len() < 4 {
+                return Ok(None);
+            }
+
+            let len = u32::from_le_bytes(rest[..4].try_into()?) as usize;
+
+            if rest.len() < 4 + len + size_of::<K>() {
+                return Ok(None);
+            }
+
+            let index_key = IndexKey(rest[4..4 + len].to_vec());
+            let key = K::from_bytes(&rest[4 + len..]);
+
+            index.entries.insert((index_key, key));
+            rest = &rest[4 + len + size_of::<K>()..];
+        }
+
+        if !rest.is_empty() || index.entries.len() != count {
+            return Ok(None);
+        }
+
+        index.changed = false;
+
+        Ok(Some(index))
+    }
+
+    /// Write the entries via a temporary file, if they changed since they were last stored.
+    pub fn store(&mut self, book_dir: &Path) -> anyhow::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let dir = Self::dir(book_dir);
+        let path = Self::path(book_dir, &self.name);
+        let tmp_path = dir.join(format!("{}.tmp", self.name));
+
+        fs::create_dir_all(&dir)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to ensure {:?}", dir)))?;
+
+        let mut buf = Vec::with_capacity(HEADER_BYTES + self.entries.len() * 16);
+        buf.extend_from_slice(&SECONDARY_INDEX_MAGIC);
+        buf.extend_from_slice(&SECONDARY_INDEX_FORMAT_VERSION.to_le_bytes());
+        buf.extend_from_slice(&(self.unique as u32).to_le_bytes());
+        buf.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+
+        for (index_key, key) in &self.entries {
+            buf.extend_from_slice(&(index_key.0.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&index_key.0);
+            key.to_bytes(&mut buf);
+        }
+
+        fs::write(&tmp_path, &buf)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", tmp_path)))?;
+        fs::File::open(&tmp_path)?.sync_all()?;
+        fs::rename(&tmp_path, &path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to write {:?}", path)))?;
+
+        self.changed = false;
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    pub fn extractor(&self) -> IndexExtractor<T> {
+        Arc::clone(&self.extractor)
+    }
+
+    #[inline]
+    pub fn extract(&self, val: &T) -> IndexKey {
+        (self.extractor)(val)
+    }
+
+    /// Check that applying `writes` together leaves no two entries sharing a
+    /// value, if the index is unique. Keys absent from `writes` keep their values.
+    pub fn check(&self, writes: &BTreeMap<K, Option<T>>) -> Result<(), ConstraintViolation<K>> {
+        if !self.unique {
+            return Ok(());
+        }
+
+        let mut claimed = HashMap::<IndexKey, K>::new();
+
+        for (key, val) in writes {
+            let val = if let Some(val) = val {
+                val
+            } else {
+                continue;
+            };
+
+            let value = self.extract(val);
+
+            let held = self
+                .get(&value)
+                .find(|other| other != key && !writes.contains_key(other));
+            let conflicting = held.or_else(|| claimed.get(&value).copied());
+
+            if let Some(conflicting) = conflicting {
+                return Err(ConstraintViolation {
+                    constraint: self.name.clone(),
+                    value,
+                    key: *key,
+                    conflicting,
+                });
+            }
+
+            claimed.insert(value, *key);
+        }
+
+        Ok(())
+    }
+
+    /// Check a single insert of a key not yet in the book against the index and
+    /// the values `claimed` by earlier inserts of the same batch. Returns the
+    /// value for the caller to claim once the insert is accepted.
+    pub fn check_insert(
+        &self,
+        key: K,
+        val: &T,
+        claimed: &HashMap<IndexKey, K>,
+    ) -> Result<IndexKey, ConstraintViolation<K>> {
+        let value = self.extract(val);
+
+        if !self.unique {
+            return Ok(value);
+        }
+
+        let conflicting = self
+            .get(&value)
+            .next()
+            .or_else(|| claimed.get(&value).copied());
+
+        match conflicting {
+            Some(conflicting) => Err(ConstraintViolation {
+                constraint: self.name.clone(),
+                value,
+                key,
+                conflicting,
+            }),
+            None => Ok(value),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, val: &T) {
+        let index_key = self.extract(val);
+
+        if self.entries.insert((index_key, key)) {
+            self.changed = true;
+        }
+    }
+
+    pub fn remove(&mut self, key: K, val: &T) {
+        let index_key = self.extract(val);
+
+        if self.entries.remove(&(index_key, key)) {
+            self.changed = true;
+        }
+    }
+
+    /// Keys of the entries indexed under `index_key`, in key order.
+    pub fn get(&self, index_key: &IndexKey) -> impl Iterator<Item = K> + '_ {
+        self.range((
+            Bound::Included(index_key.clone()),
+            Bound::Included(index_key.clone()),
+        ))
+        .map(|(_, key)| key)
+    }
+
+    /// Entries whose index key falls within `range`, ordered by index key and then key.
+    pub fn range(
+        &self,
+        range: impl RangeBounds<IndexKey>,
+    ) -> impl DoubleEndedIterator<Item = (&IndexKey, K)> + '_ {
+        let (min, max) = (K::MIN, K::MAX);
+
+        let start = match range.start_bound() {
+            Bound::Included(index_key) => Bound::Included((index_key.clone(), min)),
+            Bound::Excluded(index_key) => Bound::Excluded((index_key.clone(), max)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(index_key) => Bound::Included((index_key.clone(), max)),
+            Bound::Excluded(index_key) => Bound::Excluded((index_key.clone(), min)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        // note: `BTreeSet::range` panics on an empty range with `start > end`
+        let empty = match (&start, &end) {
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => start > end,
+            _ => false,
+        };
+
+        let range = if empty {
+            None
+        } else {
+            Some(self.entries.range((start, end)))
+        };
+
+        range
+            .into_iter()
+            .flatten()
+            .map(|(index_key, key)| (index_key, *key))
+    }
+}
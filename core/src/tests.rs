@@ -1,10 +1,38 @@
-use crate::{book::Book, BookId, Key, DATA_DIR};
+use std::{collections::BTreeSet, io::Write, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    book::Book,
+    book_builder::BookBuilder,
+    book_inner::BatchOutcome,
+    buffer_pool::FAIL_EVICTION_FLUSH,
+    changes::{ChangeEvent, ChangeKind, Lagged},
+    database::Database,
+    heap::{HeapStr, HeapVec},
+    key_index::KeyIndex,
+    ndjson::{ImportReport, OnConflict},
+    options::{BookOptions, BookOptionsBuilder, SyncPolicy},
+    page_layout::PageLayout,
+    persistable::Persistable,
+    secondary_index::{ConstraintViolation, IndexKey, SecondaryIndex},
+    verify::Problem,
+    wal::{Wal, WalRecord},
+    BookId, Idx, Key,
+};
+
+fn small_pages() -> BookOptions {
+    BookOptionsBuilder::default()
+        .page_size(256)
+        .build()
+        .expect("valid options")
+}
 
 #[test]
 fn test_create_book() -> anyhow::Result<()> {
-    std::fs::remove_dir_all(DATA_DIR.join("books/0")).ok();
+    let db = Database::temp()?;
 
-    let book: Book<u8> = Book::new(BookId::new(0)).unwrap();
+    let book: Book<u8> = Book::new_in(db.root(), BookId::new(0)).unwrap();
     let mut book_guard = book.write();
     assert_eq!(book_guard.len(), 0);
 
@@ -20,3 +48,1708 @@ fn test_create_book() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_get_update_upsert() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let book: Book<u64> = Book::new_in(db.root(), id)?;
+
+    let keys = (0..64).map(|_| Key::rand()).collect::<Vec<_>>();
+
+    for (i, key) in keys.iter().enumerate() {
+        book.write().insert(*key, i as u64)?;
+    }
+
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(book.get(*key)?, Some(i as u64));
+    }
+
+    assert_eq!(book.update(keys[3], |val| std::mem::replace(val, 300))?, 3);
+    assert_eq!(book.get(keys[3])?, Some(300));
+    assert!(book.update(Key::rand(), |val| *val += 1).is_err());
+
+    assert_eq!(book.upsert(keys[5], 500)?, Some(5));
+    assert_eq!(book.get(keys[5])?, Some(500));
+
+    let fresh = Key::rand();
+    assert_eq!(book.upsert(fresh, 42)?, None);
+    assert_eq!(book.get(fresh)?, Some(42));
+    assert_eq!(book.read().len(), 65);
+
+    book.write().delete(keys[0])?;
+    assert_eq!(book.get(keys[0])?, None);
+    assert!(!book.read().has_key(keys[0]));
+
+    Ok(())
+}
+
+#[test]
+fn test_cursor_resume() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let book: Book<u32> = Book::with_options_in(db.root(), id, small_pages())?;
+
+    for i in 0..40u32 {
+        book.write().insert(Key::new(i), i * 10)?;
+    }
+
+    for i in (0..40u32).step_by(3) {
+        book.write().delete(Key::new(i))?;
+    }
+
+    let mut all = book.iter().collect::<anyhow::Result<Vec<_>>>()?;
+    all.sort_unstable_by_key(|(key, _)| *key);
+
+    let expected = (0..40u32)
+        .filter(|i| i % 3 != 0)
+        .map(|i| (Key::new(i), i * 10))
+        .collect::<Vec<_>>();
+    assert_eq!(all, expected);
+
+    let mut cursor = book.iter();
+    let head = cursor
+        .by_ref()
+        .take(10)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let tail = book
+        .cursor_at(cursor.pos())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    assert_eq!(head.len() + tail.len(), expected.len());
+    assert!(head.iter().all(|entry| !tail.contains(entry)));
+
+    Ok(())
+}
+
+#[test]
+fn test_reopen_round_trip() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+
+    let page_count = {
+        let book: Book<u64> = Book::with_options_in(db.root(), id, small_pages())?;
+
+        for i in 0..100u32 {
+            book.write().insert(Key::new(i), i as u64)?;
+        }
+
+        for i in (0..100u32).filter(|i| i % 4 == 0) {
+            book.write().delete(Key::new(i))?;
+        }
+
+        for i in (0..100u32).filter(|i| i % 4 == 1) {
+            book.upsert(Key::new(i), i as u64 * 100)?;
+        }
+
+        book.read().page_count()
+    };
+
+    let book: Book<u64> = Book::new_in(db.root(), id)?;
+    assert_eq!(book.read().len(), 75);
+    assert_eq!(book.read().page_count(), page_count);
+
+    for i in 0..100u32 {
+        let expected = match i % 4 {
+            0 => None,
+            1 => Some(i as u64 * 100),
+            _ => Some(i as u64),
+        };
+
+        assert_eq!(book.get(Key::new(i))?, expected, "key {}", i);
+    }
+
+    // note: the vacated slots must be reused rather than growing the book
+    for i in (0..100u32).filter(|i| i % 4 == 0) {
+        book.write().insert(Key::new(i), 0)?;
+    }
+
+    assert_eq!(book.read().len(), 100);
+    assert_eq!(book.read().page_count(), page_count);
+
+    drop(book);
+
+    let book: Book<u64> = Book::new_in(db.root(), id)?;
+    assert_eq!(book.read().len(), 100);
+    assert_eq!(book.get(Key::new(8))?, Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_replay() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let book_dir = db.root().join(format!("books/{}", id.val));
+
+    {
+        let book: Book<u64> = Book::new_in(db.root(), id)?;
+
+        for i in 0..8u32 {
+            book.write().insert(Key::new(i), i as u64)?;
+        }
+    }
+
+    // note: simulate a crash after logging but before the pages were touched
+    {
+        let mut wal = Wal::open(&book_dir.join("wal"))?;
+        wal.append(
+            &WalRecord::Insert {
+                key: Key::new(100),
+                val: 100u64,
+            },
+            true,
+        )?;
+        wal.append(&WalRecord::<u64>::Delete { key: Key::new(1) }, true)?;
+        wal.append(
+            &WalRecord::Replace {
+                key: Key::new(2),
+                val: 200u64,
+            },
+            true,
+        )?;
+    }
+
+    // note: a torn record at the tail must be ignored
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(book_dir.join("wal"))?
+        .write_all(&[64, 0, 0, 0, 1, 2, 3])?;
+
+    let book: Book<u64> = Book::new_in(db.root(), id)?;
+    assert_eq!(book.read().len(), 8);
+    assert_eq!(book.get(Key::new(100))?, Some(100));
+    assert_eq!(book.get(Key::new(1))?, None);
+    assert_eq!(book.get(Key::new(2))?, Some(200));
+    assert_eq!(book.get(Key::new(3))?, Some(3));
+    assert_eq!(std::fs::metadata(book_dir.join("wal"))?.len(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_write_is_not_replayed() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let options = BookOptionsBuilder::default()
+        .page_size(256)
+        .page_cache_pages(1)
+        .build()
+        .expect("valid options");
+    let cap = PageLayout::<u64>::new(256).cap as u32;
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options.clone())?;
+
+    for i in 0..cap {
+        book.write().insert(Key::new(i), i as u64)?;
+    }
+
+    // note: the insert needs a second page, and evicting the first one fails
+    FAIL_EVICTION_FLUSH.with(|fail| fail.set(true));
+    let e = book
+        .write()
+        .insert(Key::new(cap), 0)
+        .expect_err("the write fails");
+    assert!(format!("{:#}", e).contains("write rolled back"), "{:#}", e);
+    assert_eq!(book.get(Key::new(cap))?, None);
+
+    // note: skip the checkpoint on drop, so only the log could bring the write back
+    std::mem::forget(book);
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options)?;
+    assert_eq!(book.read().len(), cap as usize);
+    assert_eq!(book.get(Key::new(cap))?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_policies() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let wal_len = |id: BookId| -> anyhow::Result<u64> {
+        Ok(std::fs::metadata(db.root().join(format!("books/{}/wal", id.val)))?.len())
+    };
+
+    let open = |id: BookId, sync_policy: SyncPolicy| -> anyhow::Result<Book<u64>> {
+        let options = BookOptionsBuilder::default()
+            .sync_policy(sync_policy)
+            .build()?;
+
+        Book::with_options_in(db.root(), id, options)
+    };
+
+    let id = BookId::rand();
+    let book = open(id, SyncPolicy::EveryWrite)?;
+    book.write().insert(Key::new(1), 1)?;
+    assert_eq!(wal_len(id)?, 0);
+    drop(book);
+
+    let id = BookId::rand();
+    let book = open(id, SyncPolicy::OnCommit)?;
+    book.write().insert(Key::new(1), 1)?;
+    assert!(wal_len(id)? > 0);
+    book.flush_async()?;
+    assert!(wal_len(id)? > 0);
+    book.flush()?;
+    assert_eq!(wal_len(id)?, 0);
+
+    // note: the last clone going away checkpoints the book
+    book.write().insert(Key::new(2), 2)?;
+    let clone = book.clone();
+    drop(book);
+    assert!(wal_len(id)? > 0);
+    drop(clone);
+    assert_eq!(wal_len(id)?, 0);
+
+    let id = BookId::rand();
+    let book = open(id, SyncPolicy::Interval(Duration::from_secs(3600)))?;
+    book.write().insert(Key::new(1), 1)?;
+    assert!(wal_len(id)? > 0);
+    drop(book);
+
+    let id = BookId::rand();
+    let book = open(id, SyncPolicy::Interval(Duration::ZERO))?;
+    book.write().insert(Key::new(1), 1)?;
+    assert_eq!(wal_len(id)?, 0);
+    drop(book);
+
+    Ok(())
+}
+
+#[test]
+fn test_transaction() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let book: Book<u64> = Book::new_in(db.root(), id)?;
+
+    for i in 0..4u32 {
+        book.write().insert(Key::new(i), i as u64)?;
+    }
+
+    let seen = book.transaction(|tx| {
+        tx.insert(Key::new(10), 10)?;
+        tx.delete(Key::new(0))?;
+        tx.update(Key::new(1), |val| *val += 100)?;
+
+        assert!(!tx.has_key(Key::new(0)));
+        assert!(tx.insert(Key::new(10), 11).is_err());
+
+        tx.get(Key::new(1))
+    })?;
+
+    assert_eq!(seen, Some(101));
+    assert_eq!(book.get(Key::new(10))?, Some(10));
+    assert_eq!(book.get(Key::new(0))?, None);
+    assert_eq!(book.get(Key::new(1))?, Some(101));
+    assert_eq!(book.read().len(), 4);
+
+    let res = book.transaction(|tx| {
+        tx.insert(Key::new(20), 20)?;
+        tx.delete(Key::new(2))?;
+        tx.delete(Key::new(0))
+    });
+
+    assert!(res.is_err());
+    assert_eq!(book.get(Key::new(20))?, None);
+    assert_eq!(book.get(Key::new(2))?, Some(2));
+    assert_eq!(book.read().len(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_transaction_rolls_back_failed_apply() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let options = BookOptionsBuilder::default()
+        .page_size(256)
+        .page_cache_pages(1)
+        .build()
+        .expect("valid options");
+    let cap = PageLayout::<u64>::new(256).cap as u32;
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options.clone())?;
+
+    for i in 0..cap - 1 {
+        book.write().insert(Key::new(i), i as u64)?;
+    }
+
+    // note: the last insert needs a second page, and evicting the first one fails
+    FAIL_EVICTION_FLUSH.with(|fail| fail.set(true));
+
+    let res = book.transaction(|tx| {
+        tx.update(Key::new(0), |val| *val += 100)?;
+        tx.delete(Key::new(1))?;
+        tx.insert(Key::new(1000), 1000)?;
+        tx.insert(Key::new(1001), 1001)?;
+        tx.insert(Key::new(1002), 1002)
+    });
+
+    let e = res.expect_err("applying the transaction fails part way");
+    assert!(
+        format!("{:#}", e).contains("transaction rolled back"),
+        "{:#}",
+        e
+    );
+
+    let check = |book: &Book<u64>| -> anyhow::Result<()> {
+        assert_eq!(book.read().len(), cap as usize - 1);
+        assert_eq!(book.get(Key::new(0))?, Some(0));
+        assert_eq!(book.get(Key::new(1))?, Some(1));
+
+        for key in 1000..1003 {
+            assert_eq!(book.get(Key::new(key))?, None);
+        }
+
+        Ok(())
+    };
+
+    check(&book)?;
+
+    // note: skip the checkpoint on drop, so only the log could bring the batch back
+    std::mem::forget(book);
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options)?;
+    check(&book)?;
+    assert!(book.verify()?.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_transaction_rolls_back_failed_read() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let options = BookOptionsBuilder::default()
+        .page_size(256)
+        .page_cache_pages(1)
+        .build()
+        .expect("valid options");
+    let cap = PageLayout::<u64>::new(256).cap as u32;
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options.clone())?;
+    book.insert_many((0..2 * cap).map(|i| (Key::new(i), i as u64)))?;
+    book.flush()?;
+
+    // note: the two keys live in different pages, so with one cached page the
+    // second write evicts the first page after it has been changed, which fails
+    FAIL_EVICTION_FLUSH.with(|fail| fail.set(true));
+
+    let e = book
+        .transaction(|tx| {
+            tx.update(Key::new(0), |val| *val += 100)?;
+            tx.update(Key::new(cap), |val| *val += 100)
+        })
+        .expect_err("the transaction fails part way");
+    assert!(
+        format!("{:#}", e).contains("transaction rolled back"),
+        "{:#}",
+        e
+    );
+    assert_eq!(book.get(Key::new(0))?, Some(0));
+    assert_eq!(book.get(Key::new(cap))?, Some(cap as u64));
+
+    // note: skip the checkpoint on drop, so only the log could bring the batch back
+    std::mem::forget(book);
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options)?;
+    assert_eq!(book.get(Key::new(0))?, Some(0));
+    assert_eq!(book.get(Key::new(cap))?, Some(cap as u64));
+    assert!(book.verify()?.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_batch_is_atomic() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let book_dir = db.root().join(format!("books/{}", id.val));
+    drop(Book::<u64>::new_in(db.root(), id)?);
+
+    let batch = [
+        WalRecord::Insert {
+            key: Key::new(1),
+            val: 1u64,
+        },
+        WalRecord::Insert {
+            key: Key::new(2),
+            val: 2u64,
+        },
+    ];
+
+    Wal::open(&book_dir.join("wal"))?.append_batch(&batch, true)?;
+    assert_eq!(Book::<u64>::new_in(db.root(), id)?.read().len(), 2);
+
+    // note: a batch torn anywhere must be dropped as a whole
+    let mut wal = Wal::open(&book_dir.join("wal"))?;
+    wal.append_batch(
+        &[
+            WalRecord::<u64>::Delete { key: Key::new(1) },
+            WalRecord::Insert {
+                key: Key::new(3),
+                val: 3,
+            },
+        ],
+        true,
+    )?;
+
+    let wal_file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(book_dir.join("wal"))?;
+    wal_file.set_len(wal.len() - 3)?;
+
+    let book = Book::<u64>::new_in(db.root(), id)?;
+    assert_eq!(book.read().len(), 2);
+    assert_eq!(book.get(Key::new(1))?, Some(1));
+    assert_eq!(book.get(Key::new(3))?, None);
+
+    Ok(())
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Persistable)]
+struct Profile {
+    age: u64,
+    tags: HeapVec<u16>,
+    email: HeapStr,
+}
+
+#[test]
+fn test_heap_values() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+
+    let first = {
+        let book: Book<Profile> = Book::new_in(db.root(), id)?;
+
+        for i in 0..16u32 {
+            let profile = Profile {
+                age: i as u64,
+                tags: book.put_vec(&(0..i as u16).collect::<Vec<_>>())?,
+                email: book.put_str(&format!("user{}@example.com", i))?,
+            };
+
+            book.write().insert(Key::new(i), profile)?;
+        }
+
+        book.get(Key::new(0))?.unwrap()
+    };
+
+    let book: Book<Profile> = Book::new_in(db.root(), id)?;
+
+    for i in 0..16u32 {
+        let profile = book.get(Key::new(i))?.unwrap();
+        assert_eq!(profile.age, i as u64);
+        assert_eq!(
+            book.get_str(profile.email)?,
+            format!("user{}@example.com", i)
+        );
+        assert_eq!(
+            book.get_vec(profile.tags)?,
+            (0..i as u16).collect::<Vec<_>>()
+        );
+    }
+
+    // note: freed blocks are only reused once a checkpoint has made the free safe
+    book.write().delete(Key::new(0))?;
+    book.free(first.email)?;
+    assert!(
+        book.free(first.email).is_err(),
+        "a handle is freed only once"
+    );
+    assert_eq!(book.get_str(first.email)?, "user0@example.com");
+    book.flush()?;
+    assert!(book.get_str(first.email).is_err());
+    assert!(
+        book.free(first.email).is_err(),
+        "a freed block is no longer live"
+    );
+
+    let reused = book.put_str("short")?;
+    assert_eq!(reused.raw.offset, first.email.raw.offset);
+    assert_eq!(book.get_str(reused)?, "short");
+
+    Ok(())
+}
+
+#[test]
+fn test_page_header_rejects_mismatch() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let book_dir = db.root().join(format!("books/{}", id.val));
+
+    {
+        let book: Book<u64> = Book::new_in(db.root(), id)?;
+        book.write().insert(Key::new(1), 1)?;
+    }
+
+    let err = Book::<i64>::new_in(db.root(), id).unwrap_err();
+    assert!(format!("{:#}", err).contains("fingerprint"), "{:#}", err);
+
+    let err = Book::<u32>::new_in(db.root(), id).unwrap_err();
+    assert!(format!("{:#}", err).contains("entries"), "{:#}", err);
+
+    let mut page = std::fs::read(book_dir.join("pages/0"))?;
+    page[0] ^= 0xff;
+    std::fs::write(book_dir.join("pages/0"), &page)?;
+
+    let err = Book::<u64>::new_in(db.root(), id).unwrap_err();
+    assert!(format!("{:#}", err).contains("magic"), "{:#}", err);
+
+    Ok(())
+}
+
+#[test]
+fn test_page_size_from_manifest() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let book_dir = db.root().join(format!("books/{}", id.val));
+
+    {
+        let book: Book<u64> = Book::with_options_in(db.root(), id, small_pages())?;
+
+        for i in 0..64u32 {
+            book.write().insert(Key::new(i), i as u64)?;
+        }
+    }
+
+    // note: the page size recorded at creation wins over the options passed on reopen
+    let book: Book<u64> = Book::new_in(db.root(), id)?;
+    assert_eq!(book.read().options().page_size, 256);
+    assert!(book.read().page_count() > 1);
+    assert_eq!(book.get(Key::new(63))?, Some(63));
+    assert_eq!(std::fs::metadata(book_dir.join("pages/0"))?.len(), 256);
+    drop(book);
+
+    // note: a book with pages is never reopened without its manifest
+    std::fs::remove_file(book_dir.join("manifest"))?;
+    assert!(Book::<u64>::new_in(db.root(), id).is_err());
+    assert!(!book_dir.join("manifest").exists());
+
+    let too_small = BookOptionsBuilder::default().page_size(32).build()?;
+    assert!(Book::<u64>::with_options_in(db.root(), BookId::rand(), too_small).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_database_catalog() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let name = "catalog".to_string();
+    let renamed = "renamed".to_string();
+
+    let book: Book<u64> = db.create_book(&name, small_pages())?;
+    book.write().insert(Key::new(1), 10)?;
+
+    assert!(db
+        .create_book::<u64>(&name, BookOptions::default())
+        .is_err());
+    assert!(db.open_book::<i64>(&name).is_err());
+    assert_eq!(db.open_book::<u64>(&name)?.get(Key::new(1))?, Some(10));
+    assert!(db.drop_book(&name).is_err());
+    drop(book);
+
+    db.rename_book(&name, &renamed)?;
+    assert!(db.open_book::<u64>(&name).is_err());
+
+    // note: databases are isolated by root, and a fresh handle reads the catalog back from disk
+    assert!(Database::temp()?.list_books().is_empty());
+
+    let db = Database::open(db.root())?;
+    let entry = db
+        .list_books()
+        .into_iter()
+        .find(|entry| entry.name == renamed)
+        .expect("renamed book is listed");
+    assert_eq!(entry.options.page_size, 256);
+    assert_eq!(db.open_book::<u64>(&renamed)?.get(Key::new(1))?, Some(10));
+
+    db.drop_book(&renamed)?;
+    assert!(db.list_books().iter().all(|entry| entry.name != renamed));
+    assert!(!db.root().join(format!("books/{}", entry.id.val)).exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_compaction() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let pages_dir = db.root().join(format!("books/{}/pages", id.val));
+
+    {
+        let book: Book<u64> = Book::with_options_in(db.root(), id, small_pages())?;
+
+        for i in 0..64u32 {
+            book.write().insert(Key::new(i), i as u64)?;
+        }
+
+        let before = book.read().page_count();
+
+        for i in (0..64u32).filter(|i| i % 4 != 0) {
+            book.write().delete(Key::new(i))?;
+        }
+
+        let reclaimed = book.compact()?;
+        assert!(reclaimed > 0);
+        assert_eq!(book.read().page_count(), before - reclaimed);
+        assert_eq!(
+            std::fs::read_dir(&pages_dir)?.count(),
+            book.read().page_count()
+        );
+        assert!(book.write().compact_step()?.is_none());
+
+        let keys = book
+            .iter()
+            .map(|entry| entry.map(|(key, _)| key.val))
+            .collect::<anyhow::Result<BTreeSet<_>>>()?;
+        assert_eq!(keys, (0..64).step_by(4).collect());
+
+        // note: reclaimed indices are handed out again as the book grows
+        for i in 64..128u32 {
+            book.write().insert(Key::new(i), i as u64)?;
+        }
+    }
+
+    let book: Book<u64> = Book::new_in(db.root(), id)?;
+    assert_eq!(book.read().len(), 16 + 64);
+    assert_eq!(book.iter().count(), 16 + 64);
+    assert_eq!(book.get(Key::new(60))?, Some(60));
+    assert_eq!(book.get(Key::new(127))?, Some(127));
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_compaction_step() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let options = BookOptionsBuilder::default()
+        .page_size(256)
+        .page_cache_pages(1)
+        .build()
+        .expect("valid options");
+    let cap = PageLayout::<u64>::new(256).cap as u32;
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options.clone())?;
+
+    for i in 0..3 * cap {
+        book.write().insert(Key::new(i), i as u64)?;
+    }
+
+    // note: the last page keeps two entries, and the others have room for one each
+    let kept = [0, cap, 2 * cap, 2 * cap + 1];
+
+    for i in [cap - 1, 2 * cap - 1]
+        .into_iter()
+        .chain(2 * cap + 2..3 * cap)
+    {
+        book.write().delete(Key::new(i))?;
+    }
+
+    book.flush()?;
+
+    // note: fetching the second target evicts the first, and that flush fails
+    FAIL_EVICTION_FLUSH.with(|fail| fail.set(true));
+
+    let e = book
+        .write()
+        .compact_step()
+        .expect_err("moving an entry fails");
+    assert!(format!("{:#}", e).contains("stopped partway"), "{:#}", e);
+
+    let check = |book: &Book<u64>| -> anyhow::Result<()> {
+        assert_eq!(book.read().len(), 2 * cap as usize);
+
+        for i in kept {
+            assert_eq!(book.get(Key::new(i))?, Some(i as u64));
+        }
+
+        assert!(book.verify()?.is_ok());
+
+        Ok(())
+    };
+
+    check(&book)?;
+
+    // note: skip the checkpoint on drop, so only the log could redo the moves
+    std::mem::forget(book);
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options)?;
+    check(&book)?;
+    assert_eq!(book.compact()?, 1);
+    check(&book)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_verify() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let page_path = db.root().join(format!("books/{}/pages/0", id.val));
+    let layout = PageLayout::<u64>::new(256);
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, small_pages())?;
+
+    for i in 0..8u32 {
+        book.write().insert(Key::new(i), i as u64)?;
+    }
+
+    let report = book.verify()?;
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!(report.entries_checked, 8);
+
+    // note: page files are mapped shared, so writes to the file show up in the book
+    let mut content = std::fs::read(&page_path)?;
+    content[layout.array_start + 4] ^= 0x01;
+    std::fs::write(&page_path, &content)?;
+
+    let problems = book.read().verify().problems;
+    assert!(
+        matches!(problems.as_slice(), [Problem::ChecksumMismatch { page, .. }] if page.val == 0),
+        "{:?}",
+        problems
+    );
+
+    content[layout.header_bytes] &= !0x01;
+    std::fs::write(&page_path, &content)?;
+
+    let problems = book.read().verify().problems;
+    assert!(
+        problems.iter().any(|problem| matches!(
+            problem,
+            Problem::BitmapMismatch {
+                on_page: None,
+                in_memory: Some(_),
+                ..
+            }
+        )),
+        "{:?}",
+        problems
+    );
+    assert!(problems
+        .iter()
+        .any(|problem| matches!(problem, Problem::DanglingLookup { .. })));
+
+    Ok(())
+}
+
+#[test]
+fn test_key_index() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let dir = db.root().to_path_buf();
+
+    let mut keys = KeyIndex::build(&dir, (0..100u32).map(|i| (Key::new(i * 2), Idx::new(i))))?;
+    assert_eq!(keys.len(), 100);
+    assert_eq!(keys.get(Key::new(42)), Some(Idx::new(21)));
+    assert_eq!(keys.get(Key::new(43)), None);
+
+    keys.insert(Key::new(43), Idx::new(7))?;
+    keys.insert(Key::new(42), Idx::new(8))?;
+    keys.remove(Key::new(0))?;
+    assert_eq!(keys.len(), 100);
+
+    let entries = keys.iter().collect::<Vec<_>>();
+    assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert_eq!(entries.len(), 100);
+
+    // note: changes made since the last sync leave the index unclean
+    assert!(KeyIndex::<Key>::open(&dir)?.is_none());
+    keys.sync()?;
+
+    let reopened = KeyIndex::<Key>::open(&dir)?.expect("index was synced");
+    assert_eq!(reopened.iter().collect::<Vec<_>>(), entries);
+
+    for i in 0..5000u32 {
+        keys.insert(Key::new(1000 + i), Idx::new(1))?;
+    }
+
+    keys.sync()?;
+    assert_eq!(std::fs::metadata(KeyIndex::<Key>::log_path(&dir))?.len(), 0);
+    assert_eq!(
+        KeyIndex::<Key>::open(&dir)?
+            .expect("index was synced")
+            .len(),
+        5100
+    );
+
+    keys.remove(Key::new(1002))?;
+    keys.insert(Key::new(1003), Idx::new(2))?;
+    keys.insert(Key::new(999), Idx::new(3))?;
+
+    let range = keys
+        .range(Key::new(998)..Key::new(1005))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        range.iter().map(|(key, _)| key.val).collect::<Vec<_>>(),
+        vec![999, 1000, 1001, 1003, 1004]
+    );
+    assert_eq!(range[3], (Key::new(1003), Idx::new(2)));
+    assert!(keys
+        .range(Key::new(998)..Key::new(1005))
+        .rev()
+        .eq(range.into_iter().rev()));
+
+    Ok(())
+}
+
+#[test]
+fn test_key_index_rebuilt_after_crash() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, small_pages())?;
+
+    for i in 0..32u32 {
+        book.write().insert(Key::new(i), i as u64)?;
+    }
+
+    book.flush()?;
+    book.write().delete(Key::new(3))?;
+    book.write().insert(Key::new(100), 100)?;
+
+    // note: skip the checkpoint on drop, as if the process had died
+    std::mem::forget(book);
+
+    let book: Book<u64> = Book::new_in(db.root(), id)?;
+    assert_eq!(book.read().len(), 32);
+    assert!(!book.read().has_key(Key::new(3)));
+    assert_eq!(book.get(Key::new(100))?, Some(100));
+    assert!(book.verify()?.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_buffer_pool_evicts() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let options = BookOptionsBuilder::default()
+        .page_size(256)
+        .page_cache_pages(2)
+        .build()
+        .expect("valid options");
+
+    {
+        let book: Book<u32> = Book::with_options_in(db.root(), id, options.clone())?;
+
+        for i in 0..200u32 {
+            book.write().insert(Key::new(i), i)?;
+        }
+
+        assert!(book.read().page_count() > 2);
+        assert!(book.read().cache_stats().resident <= 2);
+    }
+
+    let book: Book<u32> = Book::with_options_in(db.root(), id, options)?;
+    let page_count = book.read().page_count();
+
+    // note: a held page stays mapped however many others are loaded after it
+    let pinned = book.read().page(Idx::new(0))?.expect("page 0 is live");
+
+    for i in 0..200u32 {
+        assert_eq!(book.get(Key::new(i))?, Some(i));
+    }
+
+    let stats = book.read().cache_stats();
+    assert!(stats.misses >= page_count as u64, "{:?}", stats);
+    assert!(stats.evictions > 0, "{:?}", stats);
+    assert!(stats.resident <= 3, "{:?}", stats);
+    assert!(pinned.is_pinned());
+
+    drop(pinned);
+    assert!(book.verify()?.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_eviction_flush_keeps_page_dirty() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let options = BookOptionsBuilder::default()
+        .page_size(256)
+        .page_cache_pages(1)
+        .build()
+        .expect("valid options");
+    let cap = PageLayout::<u32>::new(256).cap as u32;
+
+    {
+        let book: Book<u32> = Book::with_options_in(db.root(), id, options.clone())?;
+
+        for i in 0..cap {
+            book.write().insert(Key::new(i), i)?;
+        }
+
+        // note: the next insert needs a second page, which evicts the full first one
+        FAIL_EVICTION_FLUSH.with(|fail| fail.set(true));
+        assert!(book.write().insert(Key::new(cap), cap).is_err());
+        assert_eq!(book.read().cache_stats().evictions, 0);
+
+        book.write().insert(Key::new(cap), cap)?;
+    }
+
+    let book: Book<u32> = Book::with_options_in(db.root(), id, options)?;
+
+    for i in 0..=cap {
+        assert_eq!(book.get(Key::new(i))?, Some(i));
+    }
+
+    assert!(book.verify()?.is_ok());
+
+    Ok(())
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Persistable, Serialize, Deserialize)]
+struct Account {
+    number: u64,
+    age: u32,
+    region: u32,
+}
+
+#[test]
+fn test_secondary_index() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let book_dir = db.root().join(format!("books/{}", id.val));
+
+    let account = |i: u32| Account {
+        number: 1000 + i as u64,
+        age: 20 + i % 10,
+        region: i % 3,
+    };
+
+    {
+        let book: Book<Account> = Book::with_options_in(db.root(), id, small_pages())?;
+
+        for i in 0..50u32 {
+            book.write().insert(Key::new(i), account(i))?;
+        }
+
+        // note: indexes created over existing entries are built from the pages
+        book.create_index("age", |account| account.age.into(), false)?;
+        book.create_index("number", |account| account.number.into(), true)?;
+        assert!(book
+            .create_index("region", |account| account.region.into(), true)
+            .is_err());
+
+        let aged = book.lookup_by_index("age", 23u32)?;
+        assert_eq!(
+            aged.iter().map(|(key, _)| key.val).collect::<Vec<_>>(),
+            vec![3, 13, 23, 33, 43]
+        );
+
+        let range = book.index_range("age", IndexKey::from(21u32)..IndexKey::from(23u32))?;
+        assert_eq!(range.len(), 10);
+        assert!(range.windows(2).all(|pair| pair[0].1.age <= pair[1].1.age));
+
+        let taken = Account {
+            number: 1007,
+            ..account(99)
+        };
+        assert!(book.write().insert(Key::new(99), taken).is_err());
+        assert_eq!(book.get(Key::new(99))?, None);
+
+        book.update(Key::new(3), |account| account.age = 40)?;
+        book.write().delete(Key::new(13))?;
+
+        assert_eq!(book.lookup_by_index("age", 23u32)?.len(), 3);
+        assert_eq!(
+            book.lookup_by_index("age", 40u32)?,
+            vec![(
+                Key::new(3),
+                Account {
+                    age: 40,
+                    ..account(3)
+                }
+            )]
+        );
+        assert_eq!(book.lookup_by_index("number", 1013u64)?, vec![]);
+    }
+
+    assert!(SecondaryIndex::<Account>::path(&book_dir, "age").exists());
+
+    {
+        // note: stored entries are picked up again when the index is recreated
+        let book: Book<Account> = Book::with_options_in(db.root(), id, small_pages())?;
+        book.create_index("age", |account| account.age.into(), false)?;
+        assert_eq!(book.lookup_by_index("age", 23u32)?.len(), 3);
+
+        book.rebuild_index("age")?;
+        assert_eq!(book.lookup_by_index("age", 40u32)?.len(), 1);
+
+        // note: an index that was not recreated cannot follow writes, so it is dropped
+        book.write().insert(Key::new(100), account(100))?;
+        assert!(!SecondaryIndex::<Account>::path(&book_dir, "number").exists());
+        assert!(book.lookup_by_index("number", 1100u64).is_err());
+
+        book.drop_index("age")?;
+        assert!(!SecondaryIndex::<Account>::path(&book_dir, "age").exists());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_unique_constraint() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+    let book: Book<Account> = Book::new_in(db.root(), id)?;
+
+    let account = |number: u64| Account {
+        number,
+        age: 30,
+        region: 0,
+    };
+
+    book.add_unique_constraint("number", |account| account.number.into())?;
+    book.write().insert(Key::new(1), account(100))?;
+    book.write().insert(Key::new(2), account(200))?;
+
+    let err = book.write().insert(Key::new(3), account(100)).unwrap_err();
+    let violation = err
+        .downcast_ref::<ConstraintViolation>()
+        .expect("a typed violation");
+    assert_eq!(
+        violation,
+        &ConstraintViolation {
+            constraint: "number".to_string(),
+            value: 100u64.into(),
+            key: Key::new(3),
+            conflicting: Key::new(1),
+        }
+    );
+
+    let err = book
+        .update(Key::new(2), |account| account.number = 100)
+        .unwrap_err();
+    assert!(err.downcast_ref::<ConstraintViolation>().is_some());
+    assert_eq!(book.get(Key::new(2))?, Some(account(200)));
+
+    // note: constraints hold for the transaction as a whole, so values can be swapped
+    book.transaction(|tx| {
+        tx.update(Key::new(1), |account| account.number = 200)?;
+        tx.update(Key::new(2), |account| account.number = 100)
+    })?;
+    assert_eq!(book.lookup_by_index("number", 100u64)?[0].0, Key::new(2));
+
+    let err = book
+        .transaction(|tx| {
+            tx.insert(Key::new(4), account(400))?;
+            tx.insert(Key::new(5), account(400))
+        })
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ConstraintViolation>()
+            .map(|violation| violation.conflicting),
+        Some(Key::new(4))
+    );
+    assert!(!book.read().has_key(Key::new(4)));
+
+    book.transaction(|tx| {
+        tx.delete(Key::new(1))?;
+        tx.insert(Key::new(6), account(200))
+    })?;
+    assert_eq!(book.lookup_by_index("number", 200u64)?[0].0, Key::new(6));
+    drop(book);
+
+    // note: the declaration outlives the book being closed, its extractor does not
+    let book: Book<Account> = Book::new_in(db.root(), id)?;
+    assert_eq!(
+        book.read().unique_constraints().collect::<Vec<_>>(),
+        ["number"]
+    );
+
+    let err = book.write().insert(Key::new(7), account(200)).unwrap_err();
+    assert!(err.to_string().contains("must be registered"), "{}", err);
+    assert!(book.insert_many([(Key::new(7), account(700))]).is_err());
+    assert!(book
+        .create_index("number", |account| account.number.into(), false)
+        .is_err());
+
+    book.add_unique_constraint("number", |account| account.number.into())?;
+    let err = book.write().insert(Key::new(7), account(200)).unwrap_err();
+    assert!(err.downcast_ref::<ConstraintViolation>().is_some());
+    book.write().insert(Key::new(7), account(700))?;
+    drop(book);
+
+    // note: dropping the index retracts the constraint, registered or not
+    let book: Book<Account> = Book::new_in(db.root(), id)?;
+    book.drop_index("number")?;
+    book.write().insert(Key::new(8), account(700))?;
+    drop(book);
+
+    let book: Book<Account> = Book::new_in(db.root(), id)?;
+    assert_eq!(book.read().unique_constraints().count(), 0);
+    book.write().insert(Key::new(9), account(700))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_range() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+
+    {
+        let book: Book<u32> = Book::with_options_in(db.root(), id, small_pages())?;
+
+        for i in (0..300u32).rev() {
+            book.write().insert(Key::new(i * 2), i)?;
+        }
+    }
+
+    // note: reopened so the range merges the key index base with fresh changes
+    let book: Book<u32> = Book::with_options_in(db.root(), id, small_pages())?;
+
+    for i in (0..300u32).step_by(7) {
+        book.write().delete(Key::new(i * 2))?;
+    }
+
+    book.write().insert(Key::new(101), 1000)?;
+
+    let expected = (0..300u32)
+        .filter(|i| i % 7 != 0)
+        .map(|i| (Key::new(i * 2), i))
+        .chain([(Key::new(101), 1000)])
+        .filter(|(key, _)| (20..500).contains(&key.val))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    let forward = book
+        .range(Key::new(20)..Key::new(500))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!(forward, expected);
+
+    let mut backward = book
+        .range_rev(Key::new(20)..Key::new(500))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    backward.reverse();
+    assert_eq!(backward, expected);
+
+    // note: a page of results can be resumed from where it stopped
+    let mut cursor = book.range(Key::new(20)..Key::new(500));
+    let head = cursor
+        .by_ref()
+        .take(100)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let tail = book
+        .range(cursor.bounds())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert_eq!([head, tail].concat(), expected);
+
+    let keys = book.read();
+    let mut both_ends = keys.range(Key::new(20)..=Key::new(30));
+    assert_eq!(both_ends.next().transpose()?, Some((Key::new(20), 10)));
+    assert_eq!(both_ends.next_back().transpose()?, Some((Key::new(30), 15)));
+    assert_eq!(both_ends.count(), 3);
+    drop(keys);
+
+    assert_eq!(book.range(Key::new(10)..Key::new(5)).count(), 0);
+    assert_eq!(book.range_rev(..=Key::new(4)).count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_key_types() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+
+    {
+        let wide = db.create_book_with_key::<u32, u128>("wide", small_pages())?;
+        let natural = db.create_book_with_key::<u32, [u8; 16]>("natural", small_pages())?;
+        let serial = db.create_book_with_key::<u32, u64>("serial", small_pages())?;
+
+        for i in 0..200u32 {
+            wide.write().insert((i as u128) << 64 | 7, i)?;
+            natural.write().insert(
+                *format!("user-{:011}", i).as_bytes().first_chunk().unwrap(),
+                i,
+            )?;
+            serial.write().insert(u64::MAX - i as u64, i)?;
+        }
+
+        wide.write().delete(5u128 << 64 | 7)?;
+        assert_eq!(wide.get(6u128 << 64 | 7)?, Some(6));
+        assert_eq!(wide.get(6u128 << 64)?, None);
+    }
+
+    let wide = db.open_book_with_key::<u32, u128>("wide")?;
+    assert_eq!(wide.read().len(), 199);
+    assert_eq!(wide.get(199u128 << 64 | 7)?, Some(199));
+    assert_eq!(wide.get(5u128 << 64 | 7)?, None);
+    assert_eq!(
+        wide.range(..(10u128 << 64))
+            .map(|entry| entry.map(|(_, val)| val))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        [0, 1, 2, 3, 4, 6, 7, 8, 9]
+    );
+
+    let natural = db.open_book_with_key::<u32, [u8; 16]>("natural")?;
+    assert_eq!(natural.get(*b"user-00000000042")?, Some(42));
+    assert_eq!(
+        natural
+            .range(*b"user-00000000100"..*b"user-00000000103")
+            .map(|entry| entry.map(|(_, val)| val))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        [100, 101, 102]
+    );
+
+    let serial = db.open_book_with_key::<u32, u64>("serial")?;
+    let (key, val) = serial
+        .range_rev(..)
+        .next()
+        .transpose()?
+        .expect("book is not empty");
+    assert_eq!((key, val), (u64::MAX, 0));
+    assert!(serial.read().verify().is_ok());
+
+    assert!(db.open_book::<u32>("wide").is_err());
+    assert!(db.open_book_with_key::<u32, u64>("wide").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_batch_writes() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+
+    {
+        let book: Book<Account> = Book::with_options_in(db.root(), id, small_pages())?;
+        book.add_unique_constraint("number", |account| account.number.into())?;
+
+        let account = |number: u64| Account {
+            number,
+            age: 30,
+            region: 0,
+        };
+
+        book.write().insert(Key::new(0), account(0))?;
+
+        let items = (0..2000u32).map(|i| (Key::new(i), account(i as u64)));
+        let outcomes = book.insert_many(
+            items.chain([(Key::new(5), account(9999)), (Key::new(5000), account(7))]),
+        )?;
+
+        assert_eq!(outcomes.len(), 2002);
+        assert_eq!(outcomes[0], BatchOutcome::KeyExists);
+        assert!(outcomes[1..2000].iter().all(BatchOutcome::is_applied));
+        assert_eq!(outcomes[2000], BatchOutcome::KeyExists);
+        assert!(matches!(
+            &outcomes[2001],
+            BatchOutcome::Violation(violation) if violation.conflicting == Key::new(7)
+        ));
+
+        assert_eq!(book.read().len(), 2000);
+        assert_eq!(
+            book.lookup_by_index("number", 1234u64)?[0].0,
+            Key::new(1234)
+        );
+    }
+
+    // note: reopened to read back what the batch left in the pages and key index
+    let book: Book<Account> = Book::with_options_in(db.root(), id, small_pages())?;
+    book.add_unique_constraint("number", |account| account.number.into())?;
+    assert_eq!(book.read().len(), 2000);
+    assert_eq!(
+        book.get(Key::new(1999))?.map(|account| account.number),
+        Some(1999)
+    );
+
+    let keys = (0..1000u32).step_by(2).map(Key::new);
+    let outcomes = book.delete_many(keys.chain([Key::new(2), Key::new(4321)]))?;
+    assert!(outcomes[..500].iter().all(BatchOutcome::is_applied));
+    assert_eq!(
+        outcomes[500..],
+        [BatchOutcome::KeyNotFound, BatchOutcome::KeyNotFound]
+    );
+
+    assert_eq!(book.read().len(), 1500);
+    assert_eq!(book.get(Key::new(998))?, None);
+    assert!(book.get(Key::new(999))?.is_some());
+    assert!(book.verify()?.is_ok());
+
+    // note: with one cached page, moving on to the next page evicts the last
+    // one, and failing that makes the batch fail part way
+    let id = BookId::rand();
+    let options = BookOptionsBuilder::default()
+        .page_size(256)
+        .page_cache_pages(1)
+        .build()
+        .expect("valid options");
+    let count = 2 * PageLayout::<u64>::new(256).cap as u32;
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options.clone())?;
+
+    FAIL_EVICTION_FLUSH.with(|fail| fail.set(true));
+    let e = book
+        .insert_many((0..count).map(|i| (Key::new(i), i as u64)))
+        .expect_err("the batch insert fails part way");
+    assert!(
+        format!("{:#}", e).contains("batch insert rolled back"),
+        "{:#}",
+        e
+    );
+    assert!(book.read().is_empty());
+
+    book.insert_many((0..count).map(|i| (Key::new(i), i as u64)))?;
+    book.flush()?;
+
+    FAIL_EVICTION_FLUSH.with(|fail| fail.set(true));
+    let e = book
+        .delete_many((0..count).map(Key::new))
+        .expect_err("the batch delete fails part way");
+    assert!(
+        format!("{:#}", e).contains("batch delete rolled back"),
+        "{:#}",
+        e
+    );
+    assert_eq!(book.read().len(), count as usize);
+
+    // note: skip the checkpoint on drop, so only the log could bring the batches back
+    std::mem::forget(book);
+
+    let book: Book<u64> = Book::with_options_in(db.root(), id, options)?;
+    assert_eq!(book.read().len(), count as usize);
+    assert_eq!(book.get(Key::new(0))?, Some(0));
+    assert!(book.verify()?.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_book_builder() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let id = BookId::rand();
+
+    let mut builder = BookBuilder::<u64>::new_in(db.root(), id, small_pages())?;
+    builder.extend((0..5000u32).map(|i| (Key::new(i * 3), i as u64)))?;
+    assert!(builder.push(Key::new(30), 0).is_err());
+    assert_eq!(builder.len(), 5000);
+    builder.finish()?;
+
+    assert!(BookBuilder::<u64>::new_in(db.root(), id, small_pages()).is_err());
+
+    {
+        // note: the built key index and free space map spare the open a full scan
+        let book: Book<u64> = Book::new_in(db.root(), id)?;
+        assert_eq!(book.read().cache_stats().resident, 1);
+        assert_eq!(book.read().len(), 5000);
+        assert_eq!(book.get(Key::new(4242))?, Some(1414));
+        assert_eq!(book.get(Key::new(4243))?, None);
+        assert_eq!(
+            book.range(Key::new(30)..Key::new(40))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            [
+                (Key::new(30), 10),
+                (Key::new(33), 11),
+                (Key::new(36), 12),
+                (Key::new(39), 13)
+            ]
+        );
+        assert!(book.verify()?.is_ok());
+
+        book.write().insert(Key::new(1), 1)?;
+    }
+
+    let book: Book<u64> = Book::new_in(db.root(), id)?;
+    assert_eq!(book.read().len(), 5001);
+    assert!(book.read().has_key(Key::new(1)));
+
+    // note: an abandoned build leaves nothing behind
+    let other = BookId::rand();
+    let mut builder = BookBuilder::<u64>::new_in(db.root(), other, small_pages())?;
+    builder.push(Key::new(1), 1)?;
+    drop(builder);
+    assert!(!db.root().join(format!("books/{}", other.val)).exists());
+    assert!(!db
+        .root()
+        .join(format!("books/{}.building", other.val))
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_backup_restore() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let vault = Database::temp()?;
+
+    {
+        let closed = db.create_book::<u32>("closed", small_pages())?;
+        closed.insert_many((0..100u32).map(|i| (Key::new(i), i)))?;
+    }
+
+    let open = db.create_book::<u64>("open", small_pages())?;
+    open.insert_many((0..1000u32).map(|i| (Key::new(i), i as u64)))?;
+
+    // note: a snapshot opens as a book of its own wherever it is placed
+    let id = open.read().id();
+    open.snapshot(vault.root().join(format!("books/{}", id.val)))?;
+    open.write().delete(Key::new(0))?;
+
+    let copy: Book<u64> = Book::new_in(vault.root(), id)?;
+    assert_eq!(copy.read().len(), 1000);
+    assert!(copy.verify()?.is_ok());
+    drop(copy);
+
+    let backup = vault.root().join("backup");
+    db.backup(&backup)?;
+    assert!(db.backup(&backup).is_err());
+
+    open.write().delete(Key::new(1))?;
+    assert!(
+        db.restore(&backup).is_err(),
+        "the open book blocks a restore"
+    );
+    drop(open);
+
+    db.drop_book("closed")?;
+    db.restore(&backup)?;
+
+    assert_eq!(db.list_books().len(), 2);
+    assert_eq!(db.open_book::<u32>("closed")?.get(Key::new(99))?, Some(99));
+
+    let open = db.open_book::<u64>("open")?;
+    assert_eq!(open.read().len(), 999);
+    assert_eq!(open.get(Key::new(1))?, Some(1));
+    drop(open);
+
+    // note: a damaged backup is rejected before anything is replaced
+    let page = backup.join(format!("books/{}/pages/0", id.val));
+    let mut content = std::fs::read(&page)?;
+    let last = content.len() - 1;
+    content[last] ^= 0xff;
+    std::fs::write(&page, content)?;
+
+    let err = db.restore(&backup).unwrap_err();
+    assert!(format!("{:#}", err).contains("checksum"), "{:#}", err);
+    assert_eq!(db.open_book::<u64>("open")?.read().len(), 999);
+
+    // note: a writer holding one book can open another while a backup waits for it
+    let open = db.open_book::<u64>("open")?;
+    let book_guard = open.write();
+
+    std::thread::scope(|scope| {
+        let backup = scope.spawn(|| db.backup(vault.root().join("contended")));
+        std::thread::sleep(Duration::from_millis(50));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let db = &db;
+        scope.spawn(move || {
+            let _ = tx.send(db.open_book::<u32>("closed").map(drop));
+        });
+
+        let opened = rx.recv_timeout(Duration::from_secs(5));
+        drop(book_guard);
+
+        assert!(matches!(opened, Ok(Ok(()))), "{:?}", opened);
+        backup.join().expect("backup does not panic")
+    })?;
+
+    drop(open);
+
+    // note: a book last closed by a crash is only backed up once it has been recovered
+    let crashed = db.create_book::<u32>("crashed", small_pages())?;
+    crashed.insert_many((0..100u32).map(|i| (Key::new(i), i)))?;
+    crashed.flush()?;
+    crashed.write().delete(Key::new(0))?;
+    std::mem::forget(crashed);
+
+    let restarted = Database::open(db.root())?;
+    let err = restarted.backup(vault.root().join("crashed")).unwrap_err();
+    assert!(format!("{:#}", err).contains("open it once"), "{:#}", err);
+    assert!(!vault.root().join("crashed").exists());
+
+    drop(restarted.open_book::<u32>("crashed")?);
+    restarted.backup(vault.root().join("recovered"))?;
+
+    let recovered = Database::open(vault.root().join("recovered"))?;
+    assert_eq!(recovered.open_book::<u32>("crashed")?.read().len(), 99);
+
+    Ok(())
+}
+
+#[test]
+fn test_ndjson() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+
+    let account = |number: u64| Account {
+        number,
+        age: 30,
+        region: 1,
+    };
+
+    let source = db.create_book::<Account>("source", small_pages())?;
+    source.insert_many((0..3000u32).rev().map(|i| (Key::new(i), account(i as u64))))?;
+
+    let mut exported = vec![];
+    assert_eq!(source.export_ndjson(&mut exported)?, 3000);
+
+    let text = String::from_utf8(exported.clone())?;
+    assert_eq!(text.lines().count(), 3000);
+    assert_eq!(
+        text.lines().next(),
+        Some(r#"{"key":0,"value":{"number":0,"age":30,"region":1}}"#)
+    );
+
+    let target = db.create_book::<Account>("target", small_pages())?;
+    target.add_unique_constraint("number", |account| account.number.into())?;
+
+    let report = target.import_ndjson(exported.as_slice(), OnConflict::Fail)?;
+    assert_eq!(report.inserted, 3000);
+    assert_eq!(
+        target.range(..).collect::<anyhow::Result<Vec<_>>>()?,
+        source.range(..).collect::<anyhow::Result<Vec<_>>>()?
+    );
+
+    let report = target.import_ndjson(exported.as_slice(), OnConflict::Skip)?;
+    assert_eq!(report.skipped, 3000);
+
+    let err = target
+        .import_ndjson(exported.as_slice(), OnConflict::Fail)
+        .unwrap_err();
+    assert!(err.to_string().starts_with("line 1:"), "{}", err);
+
+    // note: a failing import keeps the lines before the conflict and nothing after it
+    let partial = db.create_book::<Account>("partial", small_pages())?;
+    partial.write().insert(Key::new(3), account(3))?;
+
+    let err = partial
+        .import_ndjson(exported.as_slice(), OnConflict::Fail)
+        .unwrap_err();
+    assert!(err.to_string().starts_with("line 4:"), "{}", err);
+    assert_eq!(
+        partial
+            .range(..)
+            .map(|entry| Ok(entry?.0))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        (0..4).map(Key::new).collect::<Vec<_>>()
+    );
+
+    let changes = concat!(
+        r#"{"key":5,"value":{"number":5,"age":31,"region":2}}"#,
+        "\n\n",
+        r#"{"key":9000,"value":{"number":9000,"age":1,"region":2}}"#,
+        "\n",
+    );
+    let report = target.import_ndjson(changes.as_bytes(), OnConflict::Replace)?;
+    assert_eq!(
+        report,
+        ImportReport {
+            inserted: 1,
+            replaced: 1,
+            skipped: 0
+        }
+    );
+    assert_eq!(
+        target.get(Key::new(5))?.map(|account| account.age),
+        Some(31)
+    );
+
+    // note: replacing cannot sidestep a unique constraint
+    let clash = r#"{"key":6,"value":{"number":7,"age":1,"region":1}}"#;
+    let err = target
+        .import_ndjson(clash.as_bytes(), OnConflict::Replace)
+        .unwrap_err();
+    assert!(err.downcast_ref::<ConstraintViolation>().is_some());
+
+    let err = target
+        .import_ndjson("{}\n".as_bytes(), OnConflict::Skip)
+        .unwrap_err();
+    assert!(err.to_string().contains("line 1"), "{}", err);
+
+    let wide = db.create_book_with_key::<u32, u128>("wide", small_pages())?;
+    wide.insert_many([(u128::MAX, 1), (7, 2)])?;
+
+    let mut exported = vec![];
+    wide.export_ndjson(&mut exported)?;
+    assert_eq!(
+        String::from_utf8(exported)?,
+        format!(
+            "{{\"key\":7,\"value\":2}}\n{{\"key\":{},\"value\":1}}\n",
+            u128::MAX
+        )
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_subscribe() -> anyhow::Result<()> {
+    let db = Database::temp()?;
+    let options = BookOptionsBuilder::default()
+        .page_size(256)
+        .change_buffer(4)
+        .build()?;
+    let book: Book<u32> = Book::with_options_in(db.root(), BookId::rand(), options)?;
+
+    let mut changes = book.subscribe();
+    assert!(changes.try_recv().is_none());
+
+    book.write().insert(Key::new(1), 10)?;
+    book.update(Key::new(1), |val| *val += 1)?;
+    book.transaction(|tx| {
+        tx.delete(Key::new(1))?;
+        tx.insert(Key::new(2), 20)
+    })?;
+
+    let event = |seq, key, kind, old, new| ChangeEvent {
+        seq,
+        key: Key::new(key),
+        kind,
+        old,
+        new,
+    };
+    assert_eq!(
+        changes.by_ref().take(4).collect::<Result<Vec<_>, _>>()?,
+        [
+            event(0, 1, ChangeKind::Insert, None, Some(10)),
+            event(1, 1, ChangeKind::Replace, Some(10), Some(11)),
+            event(2, 1, ChangeKind::Delete, Some(11), None),
+            event(3, 2, ChangeKind::Insert, None, Some(20)),
+        ]
+    );
+    assert!(changes.try_recv().is_none());
+
+    // note: the subscriber falls behind once more changes pile up than it buffers
+    book.insert_many((3..8u32).map(|i| (Key::new(i), i)))?;
+    let seqs = changes
+        .by_ref()
+        .map(|event| event.map(|event| event.seq))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        seqs,
+        [Ok(4), Ok(5), Ok(6), Ok(7), Err(Lagged { resume_from: 8 })]
+    );
+
+    let resumed = book.subscribe_from(8)?;
+    assert_eq!(
+        resumed.try_recv().transpose()?.map(|event| event.key),
+        Some(Key::new(7))
+    );
+    assert!(book.subscribe_from(3).is_err());
+    assert!(book.subscribe_from(100).is_err());
+
+    let watcher = std::thread::spawn(move || {
+        resumed
+            .filter_map(|event| event.ok())
+            .map(|event| event.kind)
+            .collect::<Vec<_>>()
+    });
+
+    book.delete_many([Key::new(3), Key::new(4)])?;
+    drop(book);
+
+    assert_eq!(
+        watcher.join().expect("watcher panicked"),
+        [ChangeKind::Delete, ChangeKind::Delete]
+    );
+
+    Ok(())
+}
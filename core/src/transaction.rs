@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use crate::{
+    book_inner::BookInner, book_key::BookKey, persistable::Persistable, wal::WalRecord, Key,
+};
+
+/// Writes staged against a book, applied together when the transaction commits.
+///
+/// Reads through a transaction observe its own staged writes on top of the
+/// book's committed state.
+#[derive(Debug)]
+pub struct Transaction<'a, T: Persistable, K: BookKey = Key> {
+    book: &'a BookInner<T, K>,
+    staged: Vec<WalRecord<T, K>>,
+    overlay: HashMap<K, Option<T>>,
+}
+
+impl<'a, T: Persistable, K: BookKey> Transaction<'a, T, K> {
+    pub(crate) fn new(book: &'a BookInner<T, K>) -> Self {
+        Self {
+            book,
+            staged: vec![],
+            overlay: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn into_records(self) -> Vec<WalRecord<T, K>> {
+        self.staged
+    }
+
+    pub fn get(&self, key: K) -> anyhow::Result<Option<T>> {
+        match self.overlay.get(&key) {
+            Some(val) => Ok(*val),
+            None => self.book.get(key),
+        }
+    }
+
+    pub fn has_key(&self, key: K) -> bool {
+        match self.overlay.get(&key) {
+            Some(val) => val.is_some(),
+            None => self.book.has_key(key),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, val: T) -> anyhow::Result<()> {
+        if self.has_key(key) {
+            anyhow::bail!("key already exists");
+        }
+
+        self.stage(WalRecord::Insert { key, val });
+
+        Ok(())
+    }
+
+    pub fn upsert(&mut self, key: K, val: T) -> anyhow::Result<Option<T>> {
+        let old = self.get(key)?;
+
+        if old.is_some() {
+            self.stage(WalRecord::Replace { key, val });
+        } else {
+            self.stage(WalRecord::Insert { key, val });
+        }
+
+        Ok(old)
+    }
+
+    pub fn update<R>(&mut self, key: K, f: impl FnOnce(&mut T) -> R) -> anyhow::Result<R> {
+        let mut val = if let Some(val) = self.get(key)? {
+            val
+        } else {
+            anyhow::bail!("key not found")
+        };
+
+        let ret = f(&mut val);
+        self.stage(WalRecord::Replace { key, val });
+
+        Ok(ret)
+    }
+
+    pub fn delete(&mut self, key: K) -> anyhow::Result<()> {
+        if !self.has_key(key) {
+            anyhow::bail!("key not found");
+        }
+
+        self.stage(WalRecord::Delete { key });
+
+        Ok(())
+    }
+
+    fn stage(&mut self, record: WalRecord<T, K>) {
+        let val = match record {
+            WalRecord::Insert { val, .. } | WalRecord::Replace { val, .. } => Some(val),
+            WalRecord::Delete { .. } => None,
+        };
+
+        self.overlay.insert(record.key(), val);
+        self.staged.push(record);
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+
+use crate::{book_key::BookKey, Idx, Key};
+
+/// A single inconsistency found by `Book::verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem<K: BookKey = Key> {
+    /// The page no longer hashes to the checksum stamped at its last flush.
+    ChecksumMismatch {
+        page: Idx,
+        stored: u64,
+        computed: u64,
+    },
+    /// The page's bitmap and keys disagree with its in-memory metadata.
+    BitmapMismatch {
+        page: Idx,
+        slot: Idx,
+        on_page: Option<K>,
+        in_memory: Option<K>,
+    },
+    /// A key stored in a page that the key lookup does not point at.
+    UnindexedKey { key: K, page: Idx },
+    /// The key lookup points at a page that does not hold the key.
+    DanglingLookup { key: K, page: Idx },
+    /// The same key is stored in more than one page.
+    DuplicateKey { key: K, pages: Vec<Idx> },
+    /// The page could not be read back at all.
+    Unreadable { page: Idx, error: String },
+}
+
+impl<K: BookKey> fmt::Display for Problem<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::ChecksumMismatch {
+                page,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "page {} checksum is {:#018x}, expected {:#018x}",
+                page.val, computed, stored
+            ),
+            Problem::BitmapMismatch {
+                page,
+                slot,
+                on_page,
+                in_memory,
+            } => write!(
+                f,
+                "page {} slot {} holds {:?} on the page but {:?} in memory",
+                page.val, slot.val, on_page, in_memory
+            ),
+            Problem::UnindexedKey { key, page } => {
+                write!(f, "key {:?} in page {} is not indexed there", key, page.val)
+            }
+            Problem::DanglingLookup { key, page } => {
+                write!(
+                    f,
+                    "key {:?} is indexed in page {} but not stored there",
+                    key, page.val
+                )
+            }
+            Problem::DuplicateKey { key, pages } => write!(
+                f,
+                "key {:?} is stored in pages {:?}",
+                key,
+                pages.iter().map(|page| page.val).collect::<Vec<_>>()
+            ),
+            Problem::Unreadable { page, error } => {
+                write!(f, "page {} could not be read: {}", page.val, error)
+            }
+        }
+    }
+}
+
+/// Everything `Book::verify` checked and the problems it found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport<K: BookKey = Key> {
+    pub pages_checked: usize,
+    pub entries_checked: usize,
+    pub problems: Vec<Problem<K>>,
+}
+
+impl<K: BookKey> Default for VerifyReport<K> {
+    fn default() -> Self {
+        VerifyReport {
+            pages_checked: 0,
+            entries_checked: 0,
+            problems: vec![],
+        }
+    }
+}
+
+impl<K: BookKey> VerifyReport<K> {
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom, Write},
+    mem::{size_of, MaybeUninit},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{book_key::BookKey, persistable::Persistable, Key};
+
+const TAG_INSERT: u8 = 1;
+const TAG_REPLACE: u8 = 2;
+const TAG_DELETE: u8 = 3;
+const TAG_BATCH: u8 = 4;
+
+const LEN_BYTES: usize = size_of::<u32>();
+const CHECKSUM_BYTES: usize = 8;
+
+/// WAL size past which a book checkpoints on its own.
+pub const WAL_CHECKPOINT_BYTES: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalRecord<T: Persistable, K: BookKey = Key> {
+    Insert { key: K, val: T },
+    Replace { key: K, val: T },
+    Delete { key: K },
+}
+
+impl<T: Persistable, K: BookKey> WalRecord<T, K> {
+    #[inline]
+    pub fn key(&self) -> K {
+        match self {
+            WalRecord::Insert { key, .. }
+            | WalRecord::Replace { key, .. }
+            | WalRecord::Delete { key } => *key,
+        }
+    }
+
+    /// The value the record leaves under its key, `None` for a delete.
+    #[inline]
+    pub fn val(&self) -> Option<T> {
+        match self {
+            WalRecord::Insert { val, .. } | WalRecord::Replace { val, .. } => Some(*val),
+            WalRecord::Delete { .. } => None,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (tag, key, val) = match self {
+            WalRecord::Insert { key, val } => (TAG_INSERT, key, Some(val)),
+            WalRecord::Replace { key, val } => (TAG_REPLACE, key, Some(val)),
+            WalRecord::Delete { key } => (TAG_DELETE, key, None),
+        };
+
+        buf.push(tag);
+        key.to_bytes(buf);
+
+        if let Some(val) = val {
+            // note: values are logged bytewise, exactly as they are stored in pages
+            let bytes =
+                unsafe { std::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+            buf.extend_from_slice(bytes);
+        }
+    }
+
+    fn encode_batch(records: &[Self], buf: &mut Vec<u8>) {
+        buf.push(TAG_BATCH);
+        buf.extend_from_slice(&(records.len() as u32).to_le_bytes());
+
+        let mut sub = vec![];
+
+        for record in records {
+            sub.clear();
+            record.encode(&mut sub);
+            buf.extend_from_slice(&(sub.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&sub);
+        }
+    }
+
+    fn decode_into(body: &[u8], out: &mut Vec<Self>) -> anyhow::Result<()> {
+        if body.first() != Some(&TAG_BATCH) {
+            out.push(Self::decode(body)?);
+            return Ok(());
+        }
+
+        if body.len() < 1 + LEN_BYTES {
+            anyhow::bail!("wal batch is too short");
+        }
+
+        let count = u32::from_le_bytes(body[1..1 + LEN_BYTES].try_into()?) as usize;
+        let mut rest = &body[1 + LEN_BYTES..];
+
+        for _ in 0..count {
+            if rest.len() < LEN_BYTES {
+                anyhow::bail!("wal batch is truncated");
+            }
+
+            let sub_len = u32::from_le_bytes(rest[..LEN_BYTES].try_into()?) as usize;
+
+            if rest.len() < LEN_BYTES + sub_len {
+                anyhow::bail!("wal batch is truncated");
+            }
+
+            out.push(Self::decode(&rest[LEN_BYTES..LEN_BYTES + sub_len])?);
+            rest = &rest[LEN_BYTES + sub_len..];
+        }
+
+        Ok(())
+    }
+
+    fn decode(body: &[u8]) -> anyhow::Result<Self> {
+        if body.len() < 1 + size_of::<K>() {
+            anyhow::bail!("wal record is too short");
+        }
+
+        let tag = body[0];
+        let key = K::from_bytes(&body[1..]);
+        let rest = &body[1 + size_of::<K>()..];
+
+        let val = || -> anyhow::Result<T> {
+            if rest.len() != size_of::<T>() {
+                anyhow::bail!(
+                    "wal value is {} bytes, expected {}",
+                    rest.len(),
+                    size_of::<T>()
+                );
+            }
+
+            let mut val = MaybeUninit::<T>::uninit();
+
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    rest.as_ptr(),
+                    val.as_mut_ptr() as *mut u8,
+                    size_of::<T>(),
+                );
+
+                Ok(val.assume_init())
+            }
+        };
+
+        match tag {
+            TAG_INSERT => Ok(WalRecord::Insert { key, val: val()? }),
+            TAG_REPLACE => Ok(WalRecord::Replace { key, val: val()? }),
+            TAG_DELETE => Ok(WalRecord::Delete { key }),
+            _ => anyhow::bail!("unknown wal record tag {}", tag),
+        }
+    }
+}
+
+/// Append-only log of book mutations, written ahead of the pages they touch.
+///
+/// Each record is framed as `[len: u32][body][checksum: 8 bytes]` so that a
+/// torn write at the tail is detected and discarded on replay.
+#[derive(Debug)]
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl Wal {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to open {:?}", path)))?;
+
+        let len = file.metadata()?.len();
+
+        Ok(Wal {
+            path: path.to_path_buf(),
+            file,
+            len,
+        })
+    }
+
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append `record`, syncing it to disk before returning when `sync` is set.
+    pub fn append<T: Persistable, K: BookKey>(
+        &mut self,
+        record: &WalRecord<T, K>,
+        sync: bool,
+    ) -> anyhow::Result<()> {
+        let mut body = Vec::with_capacity(1 + size_of::<K>() + size_of::<T>());
+        record.encode(&mut body);
+
+        self.append_frame(&body, sync)
+    }
+
+    /// Append `records` as a single frame, so replay sees either all of them or none.
+    pub fn append_batch<T: Persistable, K: BookKey>(
+        &mut self,
+        records: &[WalRecord<T, K>],
+        sync: bool,
+    ) -> anyhow::Result<()> {
+        let mut body = vec![];
+        WalRecord::encode_batch(records, &mut body);
+
+        self.append_frame(&body, sync)
+    }
+
+    fn append_frame(&mut self, body: &[u8], sync: bool) -> anyhow::Result<()> {
+        let mut frame = Vec::with_capacity(LEN_BYTES + body.len() + CHECKSUM_BYTES);
+        frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        frame.extend_from_slice(body);
+        frame.extend_from_slice(&checksum(body));
+
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&frame).map_err(|e| {
+            anyhow::anyhow!(e).context(format!("failed to append to {:?}", self.path))
+        })?;
+        self.len += frame.len() as u64;
+
+        if sync {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> anyhow::Result<()> {
+        self.file
+            .sync_data()
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to sync {:?}", self.path)))?;
+
+        Ok(())
+    }
+
+    /// Read back every intact record, stopping at the first torn or corrupt frame.
+    pub fn replay<T: Persistable, K: BookKey>(&mut self) -> anyhow::Result<Vec<WalRecord<T, K>>> {
+        let mut content = Vec::with_capacity(self.len as usize);
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file
+            .read_to_end(&mut content)
+            .map_err(|e| anyhow::anyhow!(e).context(format!("failed to read {:?}", self.path)))?;
+
+        let mut records = vec![];
+        let mut rest = content.as_slice();
+
+        while rest.len() >= LEN_BYTES {
+            let body_len = u32::from_le_bytes(rest[..LEN_BYTES].try_into()?) as usize;
+            let frame_len = LEN_BYTES + body_len + CHECKSUM_BYTES;
+
+            if rest.len() < frame_len {
+                break;
+            }
+
+            let body = &rest[LEN_BYTES..LEN_BYTES + body_len];
+
+            if rest[LEN_BYTES + body_len..frame_len] != checksum(body) {
+                break;
+            }
+
+            WalRecord::decode_into(body, &mut records)?;
+            rest = &rest[frame_len..];
+        }
+
+        Ok(records)
+    }
+
+    /// Discard every record; only safe once the pages they describe are flushed.
+    pub fn truncate(&mut self) -> anyhow::Result<()> {
+        self.file.set_len(0).map_err(|e| {
+            anyhow::anyhow!(e).context(format!("failed to truncate {:?}", self.path))
+        })?;
+        self.file.sync_all()?;
+        self.len = 0;
+
+        Ok(())
+    }
+}
+
+fn checksum(body: &[u8]) -> [u8; CHECKSUM_BYTES] {
+    let digest = Sha256::digest(body);
+    let mut out = [0; CHECKSUM_BYTES];
+    out.copy_from_slice(&digest[..CHECKSUM_BYTES]);
+    out
+}
@@ -0,0 +1,114 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Type};
+
+/// Derive `Persistable` for a `#[repr(C)]` or `#[repr(transparent)]` struct.
+///
+/// Every field must itself be `Persistable`, and the struct must not contain
+/// padding, since padding bytes would be written to disk uninitialized. Fields
+/// of types with invalid byte patterns, such as `bool` and `char`, are rejected.
+#[proc_macro_derive(Persistable)]
+pub fn derive_persistable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "`Persistable` can only be derived for structs",
+            ))
+        }
+    };
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "`Persistable` cannot be derived for generic structs",
+        ));
+    }
+
+    let mut has_stable_repr = false;
+
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("repr"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("C") || meta.path.is_ident("transparent") {
+                has_stable_repr = true;
+            }
+
+            Ok(())
+        })?;
+    }
+
+    if !has_stable_repr {
+        return Err(syn::Error::new(
+            ident.span(),
+            "`Persistable` requires `#[repr(C)]` or `#[repr(transparent)]`",
+        ));
+    }
+
+    for field in fields {
+        if let Some(name) = invalid_bytes_type(&field.ty) {
+            return Err(syn::Error::new(
+                field.ty.span(),
+                format!(
+                    "`{}` is not valid for every byte pattern and cannot be persisted",
+                    name
+                ),
+            ));
+        }
+    }
+
+    let tys = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+    let padding_msg = format!("`{}` contains padding and cannot be persisted", ident);
+
+    Ok(quote! {
+        const _: () = {
+            fn assert_persistable<T: ::experimental_db_core::persistable::Persistable>() {}
+
+            let _ = || {
+                #(assert_persistable::<#tys>();)*
+            };
+
+            assert!(
+                ::core::mem::size_of::<#ident>() == 0 #(+ ::core::mem::size_of::<#tys>())*,
+                #padding_msg
+            );
+        };
+
+        unsafe impl ::experimental_db_core::persistable::Persistable for #ident {}
+    })
+}
+
+/// The name of the primitive in `ty` that some byte patterns are invalid for.
+/// Aliases are not seen through, but still fail the `Persistable` bound.
+fn invalid_bytes_type(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Array(array) => invalid_bytes_type(&array.elem),
+        Type::Group(group) => invalid_bytes_type(&group.elem),
+        Type::Paren(paren) => invalid_bytes_type(&paren.elem),
+        Type::Path(path) if path.qself.is_none() => {
+            let ident = &path.path.segments.last()?.ident;
+
+            if ident == "bool" || ident == "char" {
+                Some(ident.to_string())
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
@@ -37,7 +37,7 @@ pub fn infer_from_file(file: &syn::File) -> Schema {
 
     let mut graphs = vec![];
 
-    for (_, table) in &tables {
+    for table in tables.values() {
         if table.references.is_empty() {
             continue;
         }
@@ -66,8 +66,7 @@ pub fn infer_from_file(file: &syn::File) -> Schema {
     let mut merged_graphs = vec![];
     let graphs = &mut graphs;
 
-    while graphs.len() > 0 {
-        let mut subject = graphs.pop().unwrap();
+    while let Some(mut subject) = graphs.pop() {
         let mut targets = vec![];
 
         for (i, other) in graphs.iter().enumerate() {
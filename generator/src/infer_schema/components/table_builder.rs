@@ -127,21 +127,18 @@ impl<'ast> Visit<'ast> for TableInfoBuilder<'ast> {
         }
 
         fn get_generic_arg(arguments: &syn::PathArguments) -> Option<&syn::Ident> {
-            match arguments {
-                syn::PathArguments::AngleBracketed(ref args) => {
-                    if args.args.len() == 1 {
-                        if let syn::GenericArgument::Type(ref ty) = args.args.first().unwrap() {
-                            if let syn::Type::Path(ref ty) = ty {
-                                if ty.path.segments.len() == 1 {
-                                    let segment = ty.path.segments.last().unwrap();
-                                    return Some(&segment.ident);
-                                }
-                            }
+            if let syn::PathArguments::AngleBracketed(ref args) = arguments {
+                if args.args.len() == 1 {
+                    if let syn::GenericArgument::Type(syn::Type::Path(ref ty)) =
+                        args.args.first().unwrap()
+                    {
+                        if ty.path.segments.len() == 1 {
+                            let segment = ty.path.segments.last().unwrap();
+                            return Some(&segment.ident);
                         }
                     }
                 }
-                _ => {}
-            };
+            }
 
             None
         }
@@ -1,9 +1,6 @@
 use crate::infer_schema::{RelationKind, Schema};
 
-use syn::parse_quote;
-
 use inflection_rs::inflection;
-use prettyplease::unparse;
 use quote::format_ident;
 
 pub fn render_graph_items(schema: &Schema) -> Vec<proc_macro2::TokenStream> {
@@ -13,7 +10,7 @@ pub fn render_graph_items(schema: &Schema) -> Vec<proc_macro2::TokenStream> {
     for graph in &schema.graphs {
         let mut variants = vec![];
         let mut members_iter = graph.members.iter();
-        let mut name = format!("{}", members_iter.next().unwrap());
+        let mut name = members_iter.next().unwrap().to_string();
 
         for member in members_iter {
             name = format!("{}{}", name, member);
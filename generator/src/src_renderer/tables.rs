@@ -49,7 +49,7 @@ pub fn render_table_items(schema: &Schema) -> Vec<proc_macro2::TokenStream> {
         pub type AnyResult<T> = Result<T, Box<dyn std::error::Error>>;
     }];
 
-    all_tables.sort_by(|(a, _), (b, _)| a.cmp(b));
+    all_tables.sort_by_key(|(a, _)| *a);
 
     for (_, table_info) in all_tables {
         let mut variant_idents = vec![];